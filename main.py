@@ -122,20 +122,15 @@ def export(input_size: int = 50 * 50 * 3):
     svm_input = [("svm_input", FloatTensorType([None, pca.transform([[0] * input_size]).shape[1]]))]
 
     pca_onnx = convert_sklearn(pca, initial_types=pca_input, final_types=pca_output, target_opset=18, model_optim=True)
-    svm_onnx = convert_sklearn(svm, initial_types=svm_input, target_opset=18, model_optim=True)
+    # keep the decision function as `probabilities` next to the label, the detector thresholds it
+    svm_onnx = convert_sklearn(svm, initial_types=svm_input, target_opset=18, model_optim=True,
+                               options={id(svm): {"zipmap": False, "raw_scores": True}})
 
     pca_onnx.opset_import[0].version = 9
 
     onnx = merge_models(pca_onnx, svm_onnx, io_map=[
         ("pca_output", "svm_input")
     ])
-    remove_outputs = (o for o in onnx.graph.output if
-                      o.type.WhichOneof("value") == "sequence_type" or o.name == "probabilities")
-    for o in remove_outputs:
-        onnx.graph.output.remove(o)
-    remove_nodes = (n for n in onnx.graph.node if n.output[0] == "probabilities")
-    for n in remove_nodes:
-        onnx.graph.node.remove(n)
     with open("model.onnx", "wb") as f:
         f.write(onnx.SerializeToString())
 
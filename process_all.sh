@@ -27,17 +27,9 @@ rclone sync -P Nextcloud:ClipCutter/code/users.json .
 rclone sync -P Nextcloud:ClipCutter/code/model.onnx .
 rclone sync -P Nextcloud:ClipCutter/code/docker-compose.yaml .
 
-for channel in $(jq -r '.[] | .channel' users.json); do
-  echo "Processing $channel"
-  riot_ids=$(jq -r ".[] | select(.channel == \"$channel\") | .riot_ids | join(\",\")" users.json)
-  echo "riot_ids: $riot_ids"
-  for video in $(twitch-dl videos "$channel" --all -t archive --json | jq -r '.videos | .[] | select((now - (.publishedAt | fromdateiso8601)) < (3 * 24 * 3600)) | .id'); do
-      clean
-      echo "Processing $video";
-      docker compose run --rm --entrypoint "clip-cutter -v $video -r '$riot_ids' --remove-matches" clip_cutter || continue;
-      rclone move --ignore-existing -P clips/ Nextcloud:ClipCutter/"$channel"/ || continue;
-  done
-done
+clean
+docker compose run --rm --entrypoint "clip-cutter batch --profiles users.json --remove-matches" clip_cutter || true
+rclone move --ignore-existing -P clips/ Nextcloud:ClipCutter/ || true
 
 curl "https://status.manuel-hexe.de/api/push/VXB87TfmjS?status=up&msg=OK&ping=" || true
 
@@ -78,7 +78,6 @@ def install_pip_packages(ssh_client: SSHClient):
     run_commands(
         ssh_client,
         [
-            f"pipx install twitch-dl",
             f"pipx install youtube-dl",
         ],
     )
@@ -0,0 +1,79 @@
+use crate::error::{Error, Result};
+use crate::valorant;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use valorant_api_official::response_types::matchdetails_v1::{MatchDetailsV1, RoundResult};
+
+/// Something that happens at a known game time and shows up in the video, used to align the
+/// match video with the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorKind {
+    /// A kill of a tracked player, seen as the kill banner.
+    Kill,
+    /// The spike plant announcement.
+    Plant,
+    /// The spike defuse announcement.
+    Defuse,
+    /// The end of the buy phase.
+    RoundStart,
+}
+
+impl AnchorKind {
+    pub const ALL: [AnchorKind; 4] = [
+        AnchorKind::Kill,
+        AnchorKind::Plant,
+        AnchorKind::Defuse,
+        AnchorKind::RoundStart,
+    ];
+}
+
+/// Game times of the anchors of each kind in a match.
+pub type Anchors = BTreeMap<AnchorKind, Vec<Duration>>;
+
+/// The anchors of `valo_match`. Kills are only those of the tracked players, plants, defuses
+/// and round starts are shown to everyone in the match.
+pub fn match_anchors(valo_match: &MatchDetailsV1, puuids: &HashSet<String>) -> Result<Anchors> {
+    let anchors = Anchors::from([
+        (
+            AnchorKind::Kill,
+            valorant::get_match_kills(valo_match)
+                .iter()
+                .filter(|k| puuids.contains(&k.killer))
+                .map(|k| Duration::from_millis(k.time_since_game_start_millis))
+                .sorted()
+                .collect(),
+        ),
+        (
+            AnchorKind::Plant,
+            round_times(valo_match, |r| r.plant_round_time),
+        ),
+        (
+            AnchorKind::Defuse,
+            round_times(valo_match, |r| r.defuse_round_time),
+        ),
+        (AnchorKind::RoundStart, round_times(valo_match, |_| Some(0))),
+    ]);
+
+    if anchors.values().all(Vec::is_empty) {
+        return Err(Error::Alignment("No match anchors found".to_string()));
+    }
+    Ok(anchors)
+}
+
+/// The game times of `round_time` after the start of each round that has it.
+fn round_times(
+    valo_match: &MatchDetailsV1,
+    round_time: impl Fn(&RoundResult) -> Option<u64>,
+) -> Vec<Duration> {
+    valo_match
+        .round_results
+        .iter()
+        .flatten()
+        .filter_map(|round| Some(valorant::get_round_start_millis(round)? + round_time(round)?))
+        .map(Duration::from_millis)
+        .sorted()
+        .collect()
+}
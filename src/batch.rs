@@ -0,0 +1,74 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::pipeline::{Filters, PipelineBuilder};
+use crate::twitch;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Settings of one streamer in a batch run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Twitch login of the channel.
+    pub channel: String,
+    pub riot_ids: Vec<String>,
+    #[serde(default)]
+    pub category: Option<Vec<String>>,
+    #[serde(default)]
+    pub exclude_category: Option<Vec<String>>,
+    #[serde(default)]
+    pub only_customs: bool,
+    /// Only VODs created within this many hours are processed.
+    #[serde(default = "default_lookback_hours")]
+    pub lookback_hours: u64,
+    /// Where the clips of this channel are written to, defaults to
+    /// `{output_dir}/{channel}`.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    /// HUD profile of the streams of this channel, defaults to [`Config::hud_profile`].
+    #[serde(default)]
+    pub hud_profile: Option<String>,
+}
+
+fn default_lookback_hours() -> u64 {
+    3 * 24
+}
+
+/// Loads a JSON array of [`Profile`]s.
+pub fn load_profiles(path: &Path) -> Result<Vec<Profile>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("Failed to read {}: {e}", path.display())))?;
+    serde_json::from_str(&content)
+        .map_err(|e| Error::Config(format!("Failed to parse {}: {e}", path.display())))
+}
+
+impl Profile {
+    /// The archived VODs of the channel within the look-back window.
+    pub async fn recent_vods(&self) -> Result<Vec<usize>> {
+        let created_after =
+            OffsetDateTime::now_utc() - Duration::from_secs(self.lookback_hours * 60 * 60);
+        twitch::get_archive_vods(&self.channel, created_after).await
+    }
+
+    /// A pipeline builder with the players, filters and output directory of this profile.
+    pub fn pipeline(&self, config: &Config) -> PipelineBuilder {
+        let mut config = config.clone();
+        config.output_dir = self
+            .output_dir
+            .clone()
+            .unwrap_or_else(|| config.output_dir.join(&self.channel));
+        if let Some(hud_profile) = &self.hud_profile {
+            config.hud_profile = hud_profile.clone();
+        }
+        PipelineBuilder::default()
+            .riot_ids(self.riot_ids.iter().cloned())
+            .filters(Filters {
+                category: self.category.clone(),
+                exclude_category: self.exclude_category.clone(),
+                only_customs: self.only_customs,
+                ..Filters::default()
+            })
+            .config(config)
+    }
+}
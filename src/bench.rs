@@ -0,0 +1,302 @@
+//! Generated and recorded alignment cases to measure the accuracy and runtime of
+//! [`offset::align`].
+
+use crate::error::{Error, Result};
+use crate::offset::{self, Alignment, Track, WarpPiece, INLIER_TOLERANCE_MILLIS};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Kill times of one match with the correct mapping, generated or recorded from a real match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Case {
+    pub name: String,
+    /// Game times of the match kills.
+    pub in_match_millis: Vec<u64>,
+    /// Match video times of the detected kills, without the detector latency removed.
+    pub detected_millis: Vec<u64>,
+    /// Smallest offset considered by the search.
+    #[serde(default)]
+    pub min_offset_millis: i64,
+    /// Largest offset considered by the search.
+    #[serde(default = "default_max_offset")]
+    pub max_offset_millis: i64,
+    pub expected: Expected,
+}
+
+/// The correct mapping of a [`Case`], pieces as in [`Alignment`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expected {
+    pub offset_millis: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pieces: Vec<WarpPiece>,
+}
+
+fn default_max_offset() -> i64 {
+    offset::MAX_OFFSET_MILLIS
+}
+
+/// Settings of generated cases.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    /// Match kills per case.
+    pub kills: usize,
+    pub match_length_millis: u64,
+    /// Detected kills are moved by up to this much.
+    pub noise_millis: u64,
+    /// Share of match kills without a detected kill.
+    pub miss_rate: f64,
+    /// Detected kills without a match kill per case.
+    pub false_positives: usize,
+    /// Length of a gap in the VOD somewhere in the middle of the match, 0 for none. Half of
+    /// the cases miss that much of the VOD, so later anchors come earlier in the video, the
+    /// other half have it added, e.g. by a pause of the stream.
+    pub gap_millis: u64,
+    /// Change of the offset per game millisecond.
+    pub drift: f64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            kills: 20,
+            match_length_millis: 35 * 60 * 1000,
+            noise_millis: 150,
+            miss_rate: 0.1,
+            false_positives: 3,
+            gap_millis: 0,
+            drift: 0.,
+        }
+    }
+}
+
+/// The accuracy and runtime of [`offset::align`] over a set of cases.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub cases: usize,
+    /// Cases an alignment was found for.
+    pub aligned: usize,
+    /// Cases whose mean error is within the inlier tolerance.
+    pub correct: usize,
+    pub accuracy: f64,
+    /// Mean error of the aligned cases.
+    pub mean_error_ms: f64,
+    pub mean_confidence: f64,
+    pub mean_runtime_us: f64,
+    pub max_runtime_us: u128,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<CaseResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseResult {
+    pub name: String,
+    /// Mean distance of the found and the expected mapping at the match kills, `None` if no
+    /// alignment was found.
+    pub error_ms: Option<f64>,
+    pub confidence: f64,
+    pub runtime_us: u128,
+}
+
+/// SplitMix64, so generated cases only depend on the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `[start, end)`.
+    fn range(&mut self, start: i64, end: i64) -> i64 {
+        start + (self.unit() * (end - start) as f64) as i64
+    }
+}
+
+impl Scenario {
+    /// Generates `count` cases with offsets between 0 and [`offset::MAX_OFFSET_MILLIS`].
+    pub fn generate(&self, count: usize, seed: u64) -> Vec<Case> {
+        let mut rng = Rng(seed);
+        (0..count).map(|i| self.case(i, &mut rng)).collect()
+    }
+
+    fn case(&self, index: usize, rng: &mut Rng) -> Case {
+        let length = self.match_length_millis as i64;
+        let mut in_match = (0..self.kills)
+            .map(|_| rng.range(0, length) as u64)
+            .collect::<Vec<_>>();
+        in_match.sort_unstable();
+        in_match.dedup();
+
+        let offset_millis = rng.range(0, offset::MAX_OFFSET_MILLIS);
+        let mut pieces = vec![];
+        if self.drift != 0. || self.gap_millis > 0 {
+            pieces.push(WarpPiece {
+                from_game_millis: 0,
+                offset_millis,
+                drift: self.drift,
+            });
+        }
+        if self.gap_millis > 0 {
+            let at = rng.range(length / 5, 4 * length / 5);
+            let gap = match rng.unit() < 0.5 {
+                true => -(self.gap_millis as i64),
+                false => self.gap_millis as i64,
+            };
+            pieces.push(WarpPiece {
+                from_game_millis: at as u64,
+                offset_millis: offset_millis + (self.drift * at as f64).round() as i64 + gap,
+                drift: self.drift,
+            });
+        }
+        let expected = Expected {
+            offset_millis,
+            pieces,
+        };
+
+        let truth = expected.alignment();
+        let noise = self.noise_millis as i64;
+        let mut detected = vec![];
+        for m in &in_match {
+            if rng.unit() >= self.miss_rate {
+                detected.push(*m as i64 + truth.offset_at(*m) + rng.range(-noise, noise + 1));
+            }
+        }
+        for _ in 0..self.false_positives {
+            detected.push(rng.range(0, length + offset::MAX_OFFSET_MILLIS));
+        }
+        let mut detected = detected
+            .into_iter()
+            .filter_map(|d| u64::try_from(d).ok())
+            .collect::<Vec<_>>();
+        detected.sort_unstable();
+
+        Case {
+            name: format!("generated-{index}"),
+            in_match_millis: in_match,
+            detected_millis: detected,
+            min_offset_millis: 0,
+            max_offset_millis: offset::MAX_OFFSET_MILLIS,
+            expected,
+        }
+    }
+}
+
+impl Expected {
+    pub fn new(alignment: Alignment) -> Self {
+        Self {
+            offset_millis: alignment.offset_millis,
+            pieces: alignment.pieces,
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        Alignment {
+            pieces: self.pieces.clone(),
+            ..Alignment::manual(self.offset_millis)
+        }
+    }
+}
+
+impl Case {
+    /// Aligns the case and compares the result with the expected mapping.
+    pub fn run(&self) -> CaseResult {
+        let to_durations = |millis: &[u64]| {
+            millis
+                .iter()
+                .copied()
+                .map(Duration::from_millis)
+                .collect::<Vec<_>>()
+        };
+        let (detected, in_match) = (
+            to_durations(&self.detected_millis),
+            to_durations(&self.in_match_millis),
+        );
+        let start = Instant::now();
+        let alignment = offset::align(
+            &[Track {
+                detected: &detected,
+                in_match: &in_match,
+            }],
+            self.min_offset_millis..self.max_offset_millis,
+        );
+        let runtime_us = start.elapsed().as_micros();
+
+        let expected = self.expected.alignment();
+        let error_ms = alignment.as_ref().map(|alignment| {
+            self.in_match_millis
+                .iter()
+                .map(|m| (alignment.offset_at(*m) - expected.offset_at(*m)).abs() as f64)
+                .sum::<f64>()
+                / self.in_match_millis.len().max(1) as f64
+        });
+        CaseResult {
+            name: self.name.clone(),
+            error_ms,
+            confidence: alignment.map_or(0., |alignment| alignment.confidence),
+            runtime_us,
+        }
+    }
+}
+
+/// Runs all `cases`, keeping the result of every case with `verbose`.
+pub fn run(cases: &[Case], verbose: bool) -> Report {
+    let results = cases.iter().map(Case::run).collect::<Vec<_>>();
+    let errors = results
+        .iter()
+        .filter_map(|result| result.error_ms)
+        .collect::<Vec<_>>();
+    let correct = errors
+        .iter()
+        .filter(|error| **error <= INLIER_TOLERANCE_MILLIS as f64)
+        .count();
+    Report {
+        cases: results.len(),
+        aligned: errors.len(),
+        correct,
+        accuracy: correct as f64 / results.len().max(1) as f64,
+        mean_error_ms: mean(errors.iter().copied()),
+        mean_confidence: mean(results.iter().map(|r| r.confidence)),
+        mean_runtime_us: mean(results.iter().map(|r| r.runtime_us as f64)),
+        max_runtime_us: results.iter().map(|r| r.runtime_us).max().unwrap_or(0),
+        results: if verbose { results } else { vec![] },
+    }
+}
+
+fn mean(values: impl ExactSizeIterator<Item = f64>) -> f64 {
+    match values.len() {
+        0 => 0.,
+        len => values.sum::<f64>() / len as f64,
+    }
+}
+
+/// Loads the JSON cases in `dir`, none if it doesn't exist.
+pub fn load_fixtures(dir: &Path) -> Result<Vec<Case>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|e| Error::Config(format!("Failed to read {}: {e}", dir.display())))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| Error::Config(format!("Failed to read {}: {e}", path.display())))?;
+            serde_json::from_str(&content)
+                .map_err(|e| Error::Config(format!("Failed to parse {}: {e}", path.display())))
+        })
+        .collect()
+}
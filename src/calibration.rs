@@ -0,0 +1,79 @@
+use crate::manifest;
+use crate::offset::Alignment;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// How far a detected kill may be from where a known-good alignment puts the kill to be
+/// taken as its banner.
+const CALIBRATION_TOLERANCE_MILLIS: i64 = 1500;
+
+/// The measured lag between a kill and its banner being detected by a detection model in the
+/// area of a HUD profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// See [`model_hash`].
+    pub model_hash: String,
+    pub hud_profile: String,
+    /// Median lag of the samples.
+    pub latency_millis: i64,
+    /// Median absolute deviation of the samples from `latency_millis`.
+    pub spread_millis: i64,
+    pub samples: usize,
+    /// RFC 3339 time of the calibration.
+    pub created_at: String,
+}
+
+impl Calibration {
+    /// Calibrates the model on `hud_profile` from the lags measured by [`lags`], `None`
+    /// without samples.
+    pub fn new(model_hash: &str, hud_profile: &str, samples: &[i64]) -> Option<Self> {
+        let latency_millis = median(samples)?;
+        let deviations = samples
+            .iter()
+            .map(|sample| (sample - latency_millis).abs())
+            .collect::<Vec<_>>();
+        Some(Self {
+            model_hash: model_hash.to_string(),
+            hud_profile: hud_profile.to_string(),
+            latency_millis,
+            spread_millis: median(&deviations)?,
+            samples: samples.len(),
+            created_at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+        })
+    }
+}
+
+/// Identifies a detection model by its content, so a retrained model at the same path is
+/// calibrated again.
+pub fn model_hash(model_path: &Path) -> std::io::Result<String> {
+    Ok(format!(
+        "{:016x}",
+        manifest::fnv1a(&std::fs::read(model_path)?)
+    ))
+}
+
+/// The lags between the match kills put into the video by a known-good `alignment` and the
+/// closest detected kill of each.
+pub fn lags(detected: &[Duration], in_match: &[Duration], alignment: &Alignment) -> Vec<i64> {
+    in_match
+        .iter()
+        .filter_map(|kill| {
+            let game_millis = kill.as_millis() as u64;
+            let expected = game_millis as i64 + alignment.offset_at(game_millis);
+            detected
+                .iter()
+                .map(|d| d.as_millis() as i64 - expected)
+                .min_by_key(|lag| lag.abs())
+                .filter(|lag| lag.abs() <= CALIBRATION_TOLERANCE_MILLIS)
+        })
+        .collect()
+}
+
+fn median(values: &[i64]) -> Option<i64> {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
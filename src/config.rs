@@ -0,0 +1,289 @@
+use crate::anchors::AnchorKind;
+use crate::detector::DetectorBackend;
+use crate::error::Error;
+use crate::hud::{self, HudProfile};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Working directories and files of a run.
+///
+/// Values are taken from the defaults, then the JSON config file, then `CLIP_CUTTER_*`
+/// environment variables, each overriding the previous one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Root of the processed and failed markers.
+    pub state_dir: PathBuf,
+    /// Where match details from the Riot API are cached.
+    pub cache_dir: PathBuf,
+    /// Where match videos are downloaded to.
+    pub scratch_dir: PathBuf,
+    /// Where clips are written to.
+    pub output_dir: PathBuf,
+    /// Which detector runs on the match videos.
+    pub detector: DetectorBackend,
+    /// The kill detection model, read by the `detector`.
+    pub model_path: PathBuf,
+    /// Detection models of the other anchors, see [`Config::anchor_model`]. They are only used
+    /// with a HUD profile that has an area for the anchor.
+    pub anchor_models: BTreeMap<AnchorKind, PathBuf>,
+    /// Name of the HUD profile of the match videos, or [`hud::AUTO`] to probe each video.
+    pub hud_profile: String,
+    /// HUD profiles besides [`hud::STANDARD`] by name.
+    pub hud_profiles: BTreeMap<String, HudProfile>,
+    pub concurrency: Concurrency,
+    pub sampling: Sampling,
+    /// Ways to extract anchor events from detector scores. Alignment tries each and keeps the
+    /// most confident result, see [`Config::primary_debounce`].
+    pub debounce: Vec<Debounce>,
+    /// Matches whose alignment confidence is below this are not cut.
+    pub min_confidence: f64,
+    /// Stop the detector of an anchor once it has found as many events as the match has,
+    /// instead of running it to the end of the match video.
+    pub early_stop: bool,
+    /// Snap clips starting with a kill to the kill banner detected at a higher frame rate
+    /// around the aligned time.
+    pub refine_events: bool,
+}
+
+/// How many jobs of each pipeline stage run at the same time. Zero is treated as one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Concurrency {
+    /// VODs whose matches are discovered and processed at the same time.
+    pub vods: usize,
+    /// Matches of one VOD processed at the same time.
+    pub matches: usize,
+    /// Match videos downloaded or extracted at the same time.
+    pub downloads: usize,
+    /// Match videos run through the kill detector at the same time.
+    pub inference: usize,
+    /// ffmpeg processes cutting clips at the same time.
+    pub cuts: usize,
+}
+
+/// Frame rates of the detection passes. The whole match video is scanned at `coarse_rate`,
+/// with a `fine_rate` the parts around frames with an anchor are scanned again at that rate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sampling {
+    pub coarse_rate: f32,
+    pub fine_rate: Option<f32>,
+    /// How far before and after a frame with an anchor the fine pass looks.
+    pub fine_window_millis: u64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            coarse_rate: 3.,
+            fine_rate: None,
+            fine_window_millis: 2000,
+        }
+    }
+}
+
+/// How detector scores are turned into anchor events, see [`video::detect_kill_events`].
+///
+/// [`video::detect_kill_events`]: crate::video::detect_kill_events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Debounce {
+    /// Score above which the anchor counts as visible.
+    pub threshold: f32,
+    /// How long the anchor has to stay visible to count as an event.
+    pub min_duration_millis: u64,
+    /// How long the anchor has to be gone before the next event can start.
+    pub rearm_gap_millis: u64,
+    /// How much the score has to rise above its lowest value since the last event while the
+    /// anchor stays visible to count as a banner stacked onto it, as during multikills. Off if
+    /// unset.
+    pub stack_rise: Option<f32>,
+}
+
+impl Default for Debounce {
+    fn default() -> Self {
+        Self {
+            threshold: 0.,
+            min_duration_millis: 0,
+            rearm_gap_millis: 2000,
+            stack_rise: None,
+        }
+    }
+}
+
+fn default_debounce() -> Vec<Debounce> {
+    vec![
+        Debounce::default(),
+        Debounce {
+            min_duration_millis: 300,
+            ..Debounce::default()
+        },
+        Debounce {
+            stack_rise: Some(1.),
+            ..Debounce::default()
+        },
+    ]
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self {
+            vods: 2,
+            matches: 4,
+            downloads: 2,
+            inference: 1,
+            cuts: 4,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            state_dir: PathBuf::from("/"),
+            cache_dir: PathBuf::from("matches"),
+            scratch_dir: PathBuf::from("matches"),
+            output_dir: PathBuf::from("clips"),
+            detector: DetectorBackend::default(),
+            model_path: PathBuf::from("model.onnx"),
+            anchor_models: BTreeMap::new(),
+            hud_profile: hud::STANDARD.to_string(),
+            hud_profiles: BTreeMap::new(),
+            concurrency: Concurrency::default(),
+            sampling: Sampling::default(),
+            debounce: default_debounce(),
+            min_confidence: 0.3,
+            early_stop: false,
+            refine_events: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path` or `$CLIP_CUTTER_CONFIG` and applies the environment.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("CLIP_CUTTER_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path).map_err(|e| {
+                    Error::Config(format!("Failed to read {}: {e}", path.display()))
+                })?;
+                serde_json::from_str(&content).map_err(|e| {
+                    Error::Config(format!("Failed to parse {}: {e}", path.display()))
+                })?
+            }
+            None => Config::default(),
+        };
+        config.apply_env();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that would only fail once detection runs.
+    pub fn validate(&self) -> Result<(), Error> {
+        let rates = std::iter::once(("coarse_rate", self.sampling.coarse_rate))
+            .chain(self.sampling.fine_rate.map(|rate| ("fine_rate", rate)));
+        for (name, rate) in rates {
+            // also rejects NaN
+            if !(rate > 0. && rate.is_finite()) {
+                return Err(Error::Config(format!(
+                    "{name} must be positive, not {rate}"
+                )));
+            }
+        }
+        if self.sampling.fine_window_millis == 0 {
+            return Err(Error::Config(
+                "fine_window_millis must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The detection model of `kind`. Anchors without a model are not used for alignment.
+    pub fn anchor_model(&self, kind: AnchorKind) -> Option<&Path> {
+        match kind {
+            AnchorKind::Kill => Some(&self.model_path),
+            _ => self.anchor_models.get(&kind).map(PathBuf::as_path),
+        }
+    }
+
+    /// The event extraction used where only one is tried, the first configured one.
+    pub fn primary_debounce(&self) -> Debounce {
+        self.debounce.first().cloned().unwrap_or_default()
+    }
+
+    /// All HUD profiles by name, the standard profile first. A configured profile named
+    /// [`hud::STANDARD`] replaces the built-in one.
+    pub fn hud_profiles(&self) -> Vec<(String, HudProfile)> {
+        let standard = self
+            .hud_profiles
+            .get(hud::STANDARD)
+            .cloned()
+            .unwrap_or_default();
+        std::iter::once((hud::STANDARD.to_string(), standard))
+            .chain(
+                self.hud_profiles
+                    .iter()
+                    .filter(|(name, _)| *name != hud::STANDARD)
+                    .map(|(name, profile)| (name.clone(), profile.clone())),
+            )
+            .collect()
+    }
+
+    fn apply_env(&mut self) {
+        for (var, value) in [
+            ("CLIP_CUTTER_STATE_DIR", &mut self.state_dir),
+            ("CLIP_CUTTER_CACHE_DIR", &mut self.cache_dir),
+            ("CLIP_CUTTER_SCRATCH_DIR", &mut self.scratch_dir),
+            ("CLIP_CUTTER_OUTPUT_DIR", &mut self.output_dir),
+            ("CLIP_CUTTER_MODEL", &mut self.model_path),
+        ] {
+            if let Some(env) = std::env::var_os(var) {
+                *value = PathBuf::from(env);
+            }
+        }
+        if let Some(min_confidence) = std::env::var("CLIP_CUTTER_MIN_CONFIDENCE")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.min_confidence = min_confidence;
+        }
+        if let Ok(hud_profile) = std::env::var("CLIP_CUTTER_HUD_PROFILE") {
+            self.hud_profile = hud_profile;
+        }
+        if let Some(detector) = std::env::var("CLIP_CUTTER_DETECTOR")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.detector = detector;
+        }
+        if let Some(coarse_rate) = std::env::var("CLIP_CUTTER_COARSE_RATE")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.sampling.coarse_rate = coarse_rate;
+        }
+        if let Some(fine_rate) = std::env::var("CLIP_CUTTER_FINE_RATE")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.sampling.fine_rate = Some(fine_rate);
+        }
+        if let Some(early_stop) = std::env::var("CLIP_CUTTER_EARLY_STOP")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.early_stop = early_stop;
+        }
+        if let Some(refine_events) = std::env::var("CLIP_CUTTER_REFINE_EVENTS")
+            .ok()
+            .and_then(|env| env.parse().ok())
+        {
+            self.refine_events = refine_events;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Detector scores of match videos, stored next to the video so detection only runs again
+//! when the video, the detector or the sampling changed.
+
+use crate::anchors::AnchorKind;
+use crate::config::Sampling;
+use crate::detector::DetectorBackend;
+use crate::manifest;
+use crate::video::EarlyStop;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Bytes read from the start and the end of a video for its fingerprint.
+const FINGERPRINT_BYTES: u64 = 1 << 20;
+
+/// Everything the scores of one anchor depend on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectionKey {
+    pub kind: AnchorKind,
+    /// See [`video_fingerprint`].
+    pub video: String,
+    pub backend: DetectorBackend,
+    /// See [`calibration::model_hash`](crate::calibration::model_hash).
+    pub model_hash: String,
+    /// The [`HudProfile::filter`](crate::hud::HudProfile::filter) of the anchor.
+    pub crop: String,
+    pub seek_millis: u64,
+    pub sampling: Sampling,
+    /// Score above which the frames around a frame are scanned again at the fine rate.
+    pub threshold: f32,
+    /// When detection stopped, if it stopped early.
+    pub early_stop: Option<EarlyStop>,
+}
+
+/// The scores of one anchor in a match video.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Detections {
+    pub key: DetectionKey,
+    /// Timestamp in seconds since the seek position and the detector score of each frame.
+    pub scores: Vec<(f32, f32)>,
+}
+
+impl DetectionKey {
+    /// Where the scores of this key are stored next to `match_video_path`.
+    pub fn path(&self, match_video_path: &Path) -> PathBuf {
+        let hash = manifest::fnv1a(&serde_json::to_vec(self).unwrap());
+        let kind = serde_json::to_value(self.kind).unwrap();
+        match_video_path.with_extension(format!(
+            "{}.{hash:016x}.detections.json",
+            kind.as_str().unwrap()
+        ))
+    }
+}
+
+/// Identifies a match video by its size and its first and last [`FINGERPRINT_BYTES`], so
+/// hashing doesn't read the whole video.
+pub fn video_fingerprint(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut bytes = size.to_le_bytes().to_vec();
+    file.by_ref()
+        .take(FINGERPRINT_BYTES)
+        .read_to_end(&mut bytes)?;
+    file.seek(SeekFrom::Start(size.saturating_sub(FINGERPRINT_BYTES)))?;
+    file.read_to_end(&mut bytes)?;
+    Ok(format!("{:016x}", manifest::fnv1a(&bytes)))
+}
+
+/// The stored scores of `key`, `None` if there are none.
+pub fn load(match_video_path: &Path, key: &DetectionKey) -> Option<Vec<(f32, f32)>> {
+    let content = std::fs::read_to_string(key.path(match_video_path)).ok()?;
+    let detections: Detections = serde_json::from_str(&content).ok()?;
+    // a hash collision would give scores of a different key
+    if detections.key != *key {
+        return None;
+    }
+    debug!(
+        "Reusing {:?} detections of {}",
+        key.kind,
+        match_video_path.display()
+    );
+    Some(detections.scores)
+}
+
+/// Stores the scores of `key`, failures are only logged as detection can run again.
+pub fn save(match_video_path: &Path, key: DetectionKey, scores: Vec<(f32, f32)>) {
+    let path = key.path(match_video_path);
+    let detections = Detections { key, scores };
+    if let Err(e) = std::fs::write(&path, serde_json::to_string(&detections).unwrap()) {
+        warn!("Failed to save detections to {}: {}", path.display(), e);
+    }
+}
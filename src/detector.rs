@@ -0,0 +1,236 @@
+//! Detectors deciding whether an anchor is visible in decoded HUD frames, selected by
+//! [`Config::detector`](crate::Config::detector).
+
+use crate::error::{Error, Result};
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::OutputVideoFrame;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use ndarray::{Array, ArrayBase, CowArray, CowRepr};
+use ort::environment::Environment;
+use ort::{GraphOptimizationLevel, LoggingLevel, Session, SessionBuilder, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Width and height of the RGB frames detectors get.
+pub const FRAME_SIZE: usize = 50;
+
+/// Normalized cross-correlation above which a frame matches the template.
+const TEMPLATE_THRESHOLD: f32 = 0.6;
+
+lazy_static! {
+    static ref ORT_ENVIRONMENT: Arc<Environment> = Environment::builder()
+        .with_name("clip-cutter")
+        .with_log_level(LoggingLevel::Error)
+        .build()
+        .expect("Could not create environment")
+        .into_arc();
+    static ref DETECTORS: Mutex<HashMap<(DetectorBackend, PathBuf), Arc<dyn KillDetector>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Scores frames of the HUD area of an anchor, [`FRAME_SIZE`] square and RGB24.
+pub trait KillDetector: Send + Sync {
+    /// One score per frame, the anchor is visible in frames scoring above zero.
+    fn scores(&self, frames: &[OutputVideoFrame]) -> Result<Vec<f32>>;
+}
+
+/// The implementations of [`KillDetector`]. The model file of an anchor is read by the
+/// backend, see [`load`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectorBackend {
+    /// The ONNX classifier, the model file is the ONNX model.
+    #[default]
+    Onnx,
+    /// Compares frames with a reference picture of the anchor, the model file is an image
+    /// of the HUD area with the anchor visible.
+    Template,
+}
+
+impl FromStr for DetectorBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "onnx" => Ok(Self::Onnx),
+            "template" => Ok(Self::Template),
+            _ => Err(Error::Config(format!("Unknown detector: {s}"))),
+        }
+    }
+}
+
+impl Display for DetectorBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Onnx => write!(f, "onnx"),
+            Self::Template => write!(f, "template"),
+        }
+    }
+}
+
+/// Returns the `backend` detector with the model at `model_path`, loading it on first use.
+pub fn load(backend: DetectorBackend, model_path: &Path) -> Result<Arc<dyn KillDetector>> {
+    let mut detectors = DETECTORS.lock().unwrap();
+    let key = (backend, model_path.to_path_buf());
+    if let Some(detector) = detectors.get(&key) {
+        return Ok(detector.clone());
+    }
+    debug!("Loading {} model: {}", backend, model_path.display());
+    let detector: Arc<dyn KillDetector> = match backend {
+        DetectorBackend::Onnx => Arc::new(OnnxDetector::new(model_path)?),
+        DetectorBackend::Template => Arc::new(TemplateDetector::new(model_path)?),
+    };
+    detectors.insert(key, detector.clone());
+    Ok(detector)
+}
+
+/// Runs an ONNX classifier on the pixels of each frame, scaled to `[0, 1]`. The score of a
+/// frame is the decision function of the classifier in the last column of its
+/// [`SCORE_OUTPUT`], as exported by `main.py`. Models without it only give their label, 1 for
+/// frames with the anchor and 0 otherwise.
+pub struct OnnxDetector {
+    session: Session,
+    /// Index of [`SCORE_OUTPUT`] in the model outputs.
+    score_output: Option<usize>,
+}
+
+/// Name of the model output with the decision function of each class.
+const SCORE_OUTPUT: &str = "probabilities";
+
+impl OnnxDetector {
+    pub fn new(model_path: &Path) -> Result<Self> {
+        let session = SessionBuilder::new(&ORT_ENVIRONMENT)
+            .and_then(|builder| builder.with_optimization_level(GraphOptimizationLevel::Level3))
+            .and_then(|builder| builder.with_model_from_file(model_path))
+            .map_err(|e| Error::Detection(format!("Failed to load model: {e}")))?;
+        let score_output = session
+            .outputs
+            .iter()
+            .position(|output| output.name == SCORE_OUTPUT);
+        if score_output.is_none() {
+            warn!(
+                "Model {} has no {} output, using its labels as scores",
+                model_path.display(),
+                SCORE_OUTPUT
+            );
+        }
+        Ok(Self {
+            session,
+            score_output,
+        })
+    }
+}
+
+impl KillDetector for OnnxDetector {
+    fn scores(&self, frames: &[OutputVideoFrame]) -> Result<Vec<f32>> {
+        let array = Array::from_shape_vec(
+            (frames.len(), 3 * FRAME_SIZE * FRAME_SIZE),
+            frames
+                .iter()
+                .flat_map(|frame| frame.data.iter().map(|v| *v as f32 / 255.))
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|e| Error::Detection(format!("Unexpected frame size: {e}")))?;
+        let array: ArrayBase<CowRepr<'_, f32>, _> = CowArray::from(array).into_dyn();
+        let infer = || {
+            let tensor = Value::from_array(self.session.allocator(), &array)?;
+            let outputs = self.session.run(vec![tensor])?;
+            let Some(index) = self.score_output else {
+                let output = outputs.first().unwrap().try_extract()?;
+                let labels = output.view().iter().map(|v: &i64| *v as f32).collect();
+                return Ok(labels);
+            };
+            let output = outputs[index].try_extract::<f32>()?;
+            let output = output.view();
+            let classes = (output.len() / frames.len().max(1)).max(1);
+            let scores = output
+                .iter()
+                .skip(classes - 1)
+                .step_by(classes)
+                .copied()
+                .collect();
+            Ok(scores)
+        };
+        infer().map_err(|e: ort::OrtError| Error::Detection(format!("Inference failed: {e}")))
+    }
+}
+
+/// Matches each frame against a reference picture with normalized cross-correlation.
+pub struct TemplateDetector {
+    /// Pixels of the reference picture minus their mean.
+    template: Vec<f32>,
+    norm: f32,
+}
+
+impl TemplateDetector {
+    /// Loads the picture at `path` with ffmpeg, scaled to [`FRAME_SIZE`].
+    pub fn new(path: &Path) -> Result<Self> {
+        let mut command = FfmpegCommand::new();
+        command
+            .input(path.to_str().unwrap())
+            .filter(format!("scale={FRAME_SIZE}:{FRAME_SIZE}"))
+            .frames(1)
+            .rawvideo();
+        let frame = command
+            .spawn()
+            .map_err(|e| Error::Detection(format!("Failed to start ffmpeg: {e}")))?
+            .iter()
+            .map_err(|e| Error::Detection(format!("Failed to read template: {e}")))?
+            .filter_frames()
+            .next()
+            .ok_or_else(|| Error::Detection(format!("No image in {}", path.display())))?;
+        let template = centered(&frame.data);
+        let norm = norm(&template);
+        if norm == 0. {
+            return Err(Error::Detection(format!(
+                "Template {} is a single color",
+                path.display()
+            )));
+        }
+        Ok(Self { template, norm })
+    }
+}
+
+impl KillDetector for TemplateDetector {
+    fn scores(&self, frames: &[OutputVideoFrame]) -> Result<Vec<f32>> {
+        frames
+            .iter()
+            .map(|frame| {
+                if frame.data.len() != self.template.len() {
+                    return Err(Error::Detection(format!(
+                        "Frame has {} values, the template {}",
+                        frame.data.len(),
+                        self.template.len()
+                    )));
+                }
+                let pixels = centered(&frame.data);
+                let correlation = match norm(&pixels) {
+                    0. => 0.,
+                    frame_norm => {
+                        pixels
+                            .iter()
+                            .zip(&self.template)
+                            .map(|(a, b)| a * b)
+                            .sum::<f32>()
+                            / (frame_norm * self.norm)
+                    }
+                };
+                Ok(correlation - TEMPLATE_THRESHOLD)
+            })
+            .collect()
+    }
+}
+
+fn centered(data: &[u8]) -> Vec<f32> {
+    let mean = data.iter().map(|v| *v as f32).sum::<f32>() / data.len().max(1) as f32;
+    data.iter().map(|v| *v as f32 - mean).collect()
+}
+
+fn norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
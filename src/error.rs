@@ -0,0 +1,58 @@
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// Why processing a VOD or match failed, grouped by the pipeline stage that failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", content = "reason", rename_all = "snake_case")]
+pub enum Error {
+    /// The configuration could not be loaded.
+    Config(String),
+    /// Finding the VOD, the players or their matches failed.
+    Discovery(String),
+    /// Getting the match video failed.
+    Download(String),
+    /// Running the kill detector on the match video failed.
+    Detection(String),
+    /// Finding the offset between game time and video time failed.
+    Alignment(String),
+    /// Cutting the clips failed.
+    Cut(String),
+    /// Looking up names or writing clip metadata failed.
+    Metadata(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn stage(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::Discovery(_) => "discovery",
+            Error::Download(_) => "download",
+            Error::Detection(_) => "detection",
+            Error::Alignment(_) => "alignment",
+            Error::Cut(_) => "cut",
+            Error::Metadata(_) => "metadata",
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            Error::Config(reason)
+            | Error::Discovery(reason)
+            | Error::Download(reason)
+            | Error::Detection(reason)
+            | Error::Alignment(reason)
+            | Error::Cut(reason)
+            | Error::Metadata(reason) => reason,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed: {}", self.stage(), self.reason())
+    }
+}
+
+impl std::error::Error for Error {}
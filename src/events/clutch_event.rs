@@ -9,10 +9,10 @@ use std::time::Duration;
 use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct ClutchEvent {
-    pub(crate) clutcher: String,
-    pub(crate) kill_events: Vec<KillEvent>,
-    pub(crate) defuse_time: Option<Duration>,
+pub struct ClutchEvent {
+    pub clutcher: String,
+    pub kill_events: Vec<KillEvent>,
+    pub defuse_time: Option<Duration>,
 }
 
 impl MatchEventBuilder for ClutchEvent {
@@ -68,7 +68,7 @@ impl MatchEventBuilder for ClutchEvent {
 }
 
 impl ClutchEvent {
-    pub(crate) async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
+    pub async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
         match valorant::get_agent(valo_match, &self.clutcher) {
             Some(agent_uuid) => valorant::get_agent_name(agent_uuid).await.ok(),
             None => None,
@@ -6,11 +6,11 @@ use std::time::Duration;
 use valorant_api_official::response_types::matchdetails_v1::{MatchDetailsV1, RoundResult};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct DefuseEvent {
-    pub(crate) plant_time: Duration,
-    pub(crate) defuse_time: Duration,
-    pub(crate) planter: String,
-    pub(crate) defuser: String,
+pub struct DefuseEvent {
+    pub plant_time: Duration,
+    pub defuse_time: Duration,
+    pub planter: String,
+    pub defuser: String,
 }
 
 impl From<RoundResult> for DefuseEvent {
@@ -12,8 +12,8 @@ use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
 const KILL_TIME: u64 = 2;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct DoubleKillEvent {
-    pub(crate) kill_events: (KillEvent, KillEvent),
+pub struct DoubleKillEvent {
+    pub kill_events: (KillEvent, KillEvent),
 }
 
 impl MatchEventBuilder for DoubleKillEvent {
@@ -49,7 +49,7 @@ impl MatchEventBuilder for DoubleKillEvent {
 }
 
 impl DoubleKillEvent {
-    pub(crate) async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
+    pub async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
         let agent_uuid = valorant::get_agent(valo_match, &self.kill_events.0.killer);
         match agent_uuid {
             Some(agent_uuid) => valorant::get_agent_name(agent_uuid).await.ok(),
@@ -57,7 +57,7 @@ impl DoubleKillEvent {
         }
     }
 
-    pub(crate) async fn get_death_agents(&self, valo_match: &MatchDetailsV1) -> Vec<String> {
+    pub async fn get_death_agents(&self, valo_match: &MatchDetailsV1) -> Vec<String> {
         futures::future::join_all(
             self.kill_events
                 .clone()
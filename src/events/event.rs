@@ -12,7 +12,8 @@ use std::fmt::Debug;
 use std::time::Duration;
 use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
 
-pub(crate) trait MatchEvent: Debug + Clone + Serialize {
+#[allow(async_fn_in_trait)]
+pub trait MatchEvent: Debug + Clone + Serialize {
     async fn category(&self, puuids: &HashSet<String>) -> String;
     async fn name_postfix(&self, valo_match: &MatchDetailsV1) -> String;
     fn game_time_interval(&self) -> (Duration, Duration);
@@ -20,13 +21,13 @@ pub(crate) trait MatchEvent: Debug + Clone + Serialize {
     fn is_against_puuids(&self, puuids: &HashSet<String>) -> bool;
 }
 
-pub(crate) trait MatchEventBuilder {
+pub trait MatchEventBuilder {
     fn build_events(valo_match: &MatchDetailsV1) -> Vec<Box<Self>>;
 }
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
-pub(crate) enum Event {
+pub enum Event {
     Kill(KillEvent),
     MultiKill(MultiKillEvent),
     Clutch(ClutchEvent),
@@ -37,7 +38,7 @@ pub(crate) enum Event {
     Retake(RetakeEvent),
 }
 
-pub(crate) fn build_events(valo_match: &MatchDetailsV1) -> Vec<Event> {
+pub fn build_events(valo_match: &MatchDetailsV1) -> Vec<Event> {
     [
         KillEvent::build_events(valo_match)
             .into_iter()
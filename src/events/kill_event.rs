@@ -11,12 +11,12 @@ use valorant_api_official::response_types::matchdetails_v1::{
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct KillEvent {
-    pub(crate) game_time: Duration,
-    pub(crate) killer: String,
-    pub(crate) victim: String,
-    pub(crate) finishing_damage: KillFinishingDamage,
-    pub(crate) shots: Option<(u32, u32, u32)>,
+pub struct KillEvent {
+    pub game_time: Duration,
+    pub killer: String,
+    pub victim: String,
+    pub finishing_damage: KillFinishingDamage,
+    pub shots: Option<(u32, u32, u32)>,
 }
 
 impl From<PlayerRoundKill> for KillEvent {
@@ -74,7 +74,7 @@ impl MatchEventBuilder for KillEvent {
 }
 
 impl KillEvent {
-    pub(crate) async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
+    pub async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
         let agent_uuid = self.get_agent_uuid(valo_match, &self.killer);
         match agent_uuid {
             Some(agent_uuid) => valorant::get_agent_name(agent_uuid).await.ok(),
@@ -82,7 +82,7 @@ impl KillEvent {
         }
     }
 
-    pub(crate) async fn get_death_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
+    pub async fn get_death_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
         let agent_uuid = self.get_agent_uuid(valo_match, &self.victim);
         match agent_uuid {
             Some(agent_uuid) => valorant::get_agent_name(agent_uuid).await.ok(),
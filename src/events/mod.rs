@@ -1,11 +1,11 @@
-pub(crate) mod ace_event;
-pub(crate) mod clutch_event;
-pub(crate) mod defuse_event;
-pub(crate) mod doublekill_event;
-pub(crate) mod event;
-pub(crate) mod kill_event;
-pub(crate) mod multikill_event;
-pub(crate) mod plant_event;
-pub(crate) mod retake_event;
+pub mod ace_event;
+pub mod clutch_event;
+pub mod defuse_event;
+pub mod doublekill_event;
+pub mod event;
+pub mod kill_event;
+pub mod multikill_event;
+pub mod plant_event;
+pub mod retake_event;
 
-pub(crate) use event::*;
+pub use event::*;
@@ -9,8 +9,8 @@ use std::time::Duration;
 use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct MultiKillEvent {
-    pub(crate) kill_events: Vec<KillEvent>,
+pub struct MultiKillEvent {
+    pub kill_events: Vec<KillEvent>,
 }
 
 impl MatchEventBuilder for MultiKillEvent {
@@ -56,7 +56,7 @@ impl MatchEventBuilder for MultiKillEvent {
 }
 
 impl MultiKillEvent {
-    pub(crate) async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
+    pub async fn get_kill_agent(&self, valo_match: &MatchDetailsV1) -> Option<String> {
         let agent_uuid = valorant::get_agent(valo_match, &self.kill_events[0].killer);
         match agent_uuid {
             Some(agent_uuid) => valorant::get_agent_name(agent_uuid).await.ok(),
@@ -64,7 +64,7 @@ impl MultiKillEvent {
         }
     }
 
-    pub(crate) async fn get_death_agents(&self, valo_match: &MatchDetailsV1) -> Vec<String> {
+    pub async fn get_death_agents(&self, valo_match: &MatchDetailsV1) -> Vec<String> {
         futures::future::join_all(
             self.kill_events
                 .iter()
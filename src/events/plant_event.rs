@@ -6,9 +6,9 @@ use std::time::Duration;
 use valorant_api_official::response_types::matchdetails_v1::{MatchDetailsV1, RoundResult};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct PlantEvent {
-    pub(crate) plant_time: Duration,
-    pub(crate) planter: String,
+pub struct PlantEvent {
+    pub plant_time: Duration,
+    pub planter: String,
 }
 
 impl From<RoundResult> for PlantEvent {
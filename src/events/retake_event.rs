@@ -9,11 +9,11 @@ use valorant_api_official::response_types::matchdetails_v1::{
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct RetakeEvent {
-    pub(crate) winners: HashSet<String>,
-    pub(crate) losers: HashSet<String>,
-    pub(crate) plant_time: Duration,
-    pub(crate) defuse_time: Duration,
+pub struct RetakeEvent {
+    pub winners: HashSet<String>,
+    pub losers: HashSet<String>,
+    pub plant_time: Duration,
+    pub defuse_time: Duration,
 }
 
 fn is_attacker(round_num: u8, team: &TeamUnion) -> bool {
@@ -35,7 +35,7 @@ fn is_red_attacker(round_num: u8) -> bool {
     if round_num < 24 {
         return false;
     }
-    round_num % 2 == 0
+    round_num.is_multiple_of(2)
 }
 
 impl RetakeEvent {
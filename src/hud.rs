@@ -0,0 +1,144 @@
+//! Where the anchors are on screen for different HUD layouts and stream canvases.
+
+use crate::anchors::AnchorKind;
+use crate::config::Config;
+use crate::detector::FRAME_SIZE;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Profile name that picks the profile per match video, see [`Pipeline::hud_profile`].
+///
+/// [`Pipeline::hud_profile`]: crate::Pipeline::hud_profile
+pub const AUTO: &str = "auto";
+/// The built-in profile of an unscaled 16:9 game capture with the default HUD.
+pub const STANDARD: &str = "standard";
+
+/// A rectangle in units of the video width and height.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CropRect {
+    /// Horizontal center.
+    pub center_x: f64,
+    /// Top edge.
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// The screen areas of the anchors and the models detecting them for one HUD layout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HudProfile {
+    /// Area of each anchor. Kills without one use the area the kill model was trained on, other
+    /// anchors without one are not detected.
+    #[serde(default)]
+    pub crops: BTreeMap<AnchorKind, CropRect>,
+    /// The in-game HUD scale, the areas are scaled by it around their center.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Detection models trained on this layout, anchors without one use the configured
+    /// model.
+    #[serde(default)]
+    pub models: BTreeMap<AnchorKind, PathBuf>,
+}
+
+fn default_scale() -> f64 {
+    1.
+}
+
+impl Default for HudProfile {
+    fn default() -> Self {
+        Self {
+            crops: BTreeMap::new(),
+            scale: default_scale(),
+            models: BTreeMap::new(),
+        }
+    }
+}
+
+impl HudProfile {
+    /// The area of `kind`, unscaled.
+    pub fn crop(&self, kind: AnchorKind) -> Option<CropRect> {
+        self.crops
+            .get(&kind)
+            .copied()
+            .or_else(|| standard_crop(kind))
+    }
+
+    /// The ffmpeg filter cutting the area of `kind` out of a frame, scaled to [`FRAME_SIZE`].
+    pub fn filter(&self, kind: AnchorKind) -> Option<String> {
+        let crop_filter = self.crop_filter(kind)?;
+        Some(format!("{crop_filter},scale={FRAME_SIZE}:{FRAME_SIZE}"))
+    }
+
+    /// The ffmpeg filter cutting the area of `kind` out of a frame at the video resolution.
+    /// The edges are rounded to whole pixels, so the standard profile cuts exactly the area the
+    /// kill model was trained on, `crop=200:200:in_w/2-100:0.7*in_h` in a 1080p capture.
+    pub fn crop_filter(&self, kind: AnchorKind) -> Option<String> {
+        let crop = self.crop(kind)?;
+        let (width, height) = (crop.width * self.scale, crop.height * self.scale);
+        let top = crop.top + (crop.height - height) / 2.;
+        Some(format!(
+            "crop=round(in_w*{width}):round(in_h*{height}):round(in_w*{}):round(in_h*{top})",
+            crop.center_x - width / 2.
+        ))
+    }
+
+    /// The detection model of `kind`, see [`Config::anchor_model`].
+    pub fn model<'a>(&'a self, config: &'a Config, kind: AnchorKind) -> Option<&'a Path> {
+        self.models
+            .get(&kind)
+            .map(PathBuf::as_path)
+            .or_else(|| config.anchor_model(kind))
+    }
+}
+
+/// The area of the kill banner in a 1080p game capture. The other anchors have no measured
+/// area yet, so they need one in the profile.
+fn standard_crop(kind: AnchorKind) -> Option<CropRect> {
+    (kind == AnchorKind::Kill).then_some(CropRect {
+        center_x: 0.5,
+        top: 0.7,
+        width: 200. / 1920.,
+        height: 200. / 1080.,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates the `round(in_w*x)` and `round(in_h*x)` values of a crop filter like ffmpeg.
+    fn crop_pixels(filter: &str, in_w: f64, in_h: f64) -> Vec<i64> {
+        filter
+            .trim_start_matches("crop=")
+            .split(':')
+            .map(|value| {
+                let value = value.trim_start_matches("round(").trim_end_matches(')');
+                let (size, factor) = value.split_once('*').unwrap();
+                let size = if size == "in_w" { in_w } else { in_h };
+                (size * factor.parse::<f64>().unwrap()).round() as i64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn standard_kill_crop_matches_training_crop() {
+        let filter = HudProfile::default().crop_filter(AnchorKind::Kill).unwrap();
+        // crop=200:200:in_w/2-100:0.7*in_h
+        assert_eq!(crop_pixels(&filter, 1920., 1080.), [200, 200, 860, 756]);
+    }
+
+    #[test]
+    fn other_anchors_need_a_crop_in_the_profile() {
+        let mut profile = HudProfile::default();
+        assert_eq!(profile.filter(AnchorKind::Plant), None);
+        let crop = CropRect {
+            center_x: 0.5,
+            top: 0.1,
+            width: 0.2,
+            height: 0.1,
+        };
+        profile.crops.insert(AnchorKind::Plant, crop);
+        assert_eq!(profile.crop(AnchorKind::Plant), Some(crop));
+    }
+}
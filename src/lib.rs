@@ -0,0 +1,26 @@
+pub mod anchors;
+pub mod batch;
+pub mod bench;
+pub mod calibration;
+pub mod config;
+pub mod detections;
+pub mod detector;
+pub mod error;
+pub mod events;
+pub mod hud;
+pub mod local;
+pub mod manifest;
+pub mod offset;
+pub mod pipeline;
+pub mod stages;
+pub mod state;
+pub mod twitch;
+pub mod valorant;
+pub mod video;
+
+pub use config::Config;
+pub use error::Error;
+pub use pipeline::{
+    ClipResult, Filters, MatchPlan, MatchResult, Pipeline, PipelineBuilder, Plan, PlannedClip,
+    VodPlan, VodResult, VodSource,
+};
@@ -0,0 +1,122 @@
+use crate::error::Error;
+use crate::video;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(Debug, Deserialize)]
+struct FfprobeOutput {
+    format: FfprobeFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobeFormat {
+    duration: Option<String>,
+    #[serde(default)]
+    tags: FfprobeTags,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FfprobeTags {
+    creation_time: Option<String>,
+}
+
+/// Returns the wall-clock interval covered by a local recording.
+///
+/// The start is taken from `start` if given, otherwise from the `creation_time` tag embedded in
+/// the container and finally from the creation time of the file itself.
+pub fn get_video_start_end(
+    path: &Path,
+    start: Option<OffsetDateTime>,
+) -> Result<(OffsetDateTime, OffsetDateTime), Error> {
+    let probe = probe(path)
+        .map_err(|e| Error::Discovery(format!("Failed to probe {}: {e}", path.display())))?;
+    let start_time = start
+        .or_else(|| {
+            probe
+                .format
+                .tags
+                .creation_time
+                .as_deref()
+                .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok())
+        })
+        .or_else(|| {
+            warn!(
+                "No creation_time metadata in {}, falling back to file creation time",
+                path.display()
+            );
+            std::fs::metadata(path)
+                .and_then(|m| m.created())
+                .ok()
+                .map(OffsetDateTime::from)
+        })
+        .ok_or_else(|| {
+            Error::Discovery(format!(
+                "Failed to determine start time of {}",
+                path.display()
+            ))
+        })?;
+    let length = probe
+        .format
+        .duration
+        .and_then(|d| d.parse::<f64>().ok())
+        .ok_or_else(|| Error::Discovery(format!("Unknown duration of {}", path.display())))?;
+    let end_time = start_time + Duration::from_secs_f64(length);
+    Ok((start_time, end_time))
+}
+
+/// Returns the length of a video file.
+pub fn get_duration(path: &Path) -> std::io::Result<Duration> {
+    probe(path)?
+        .format
+        .duration
+        .and_then(|d| d.parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| std::io::Error::other(format!("Unknown duration of {}", path.display())))
+}
+
+fn probe(path: &Path) -> std::io::Result<FfprobeOutput> {
+    let mut cmd = std::process::Command::new("ffprobe");
+    cmd.arg("-v")
+        .arg("quiet")
+        .arg("-show_entries")
+        .arg("format=duration:format_tags=creation_time")
+        .arg("-of")
+        .arg("json")
+        .arg(path);
+    debug!("Running command: {:?}", cmd);
+    let output = cmd.output()?.stdout;
+    serde_json::from_slice(&output).map_err(std::io::Error::other)
+}
+
+/// Copies the part between `start` and `end` of a local recording to `out_path`. The part is
+/// written next to it first and only moved there once ffmpeg succeeded, so an existing
+/// `out_path` is always complete.
+pub fn save_video_part(
+    path: &Path,
+    out_path: &Path,
+    start: Duration,
+    end: Duration,
+) -> std::io::Result<()> {
+    if out_path.exists() {
+        debug!("Video already exists: {}", out_path.display());
+        return Ok(());
+    }
+    info!(
+        "Saving video part of {} from {:?} to {:?}",
+        path.display(),
+        start,
+        end
+    );
+    std::fs::create_dir_all(out_path.parent().unwrap())?;
+    let extension = out_path.extension().unwrap_or_default().to_string_lossy();
+    let part_path = out_path.with_extension(format!("part.{extension}"));
+    if let Err(e) = video::split_video(path, &part_path, start, end, true, None) {
+        std::fs::remove_file(&part_path).ok();
+        return Err(e);
+    }
+    std::fs::rename(part_path, out_path)
+}
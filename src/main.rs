@@ -1,48 +1,249 @@
-mod events;
-mod offset;
-mod twitch;
-mod valorant;
-mod video;
-
-use crate::events::Event;
-use crate::video::Metadata;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use clip_cutter::calibration::Calibration;
+use clip_cutter::detector::DetectorBackend;
+use clip_cutter::{batch, bench, stages, Config, Error, Filters, Pipeline, VodSource};
 use dotenv::dotenv;
-use events::MatchEvent;
-use filetime_creation::{set_file_times, FileTime};
-use itertools::Itertools;
-use kdam::tqdm;
-use lazy_static::lazy_static;
-use log::{debug, error, info};
-use std::collections::HashSet;
+use log::{error, info};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::path::Path;
-use std::time::{Duration, SystemTime};
-use time::{format_description, OffsetDateTime};
-use valorant_api_official::enums::queue::Queue;
-use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
-
-lazy_static! {
-    static ref CLIP_DATE_TIME_PREFIX: Vec<format_description::FormatItem<'static>> =
-        format_description::parse("[day]-[month]-[year]_[hour]-[minute]-[second]").unwrap();
-    static ref CLIP_DATE_PREFIX: Vec<format_description::FormatItem<'static>> =
-        format_description::parse("[day]-[month]-[year]").unwrap();
-    static ref CLIP_PADDING: (Duration, Duration) =
-        (Duration::from_secs(10), Duration::from_secs(10));
-}
+use std::path::PathBuf;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-#[derive(Parser, Debug, Clone, Eq, Hash, PartialEq)]
+#[derive(Parser, Debug, Clone, PartialEq)]
 #[command(author, version, about, long_about = None)]
 #[command(next_line_help = true)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    #[arg(short, long, required = true)]
-    vod_ids: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    sources: SourcesArgs,
     #[arg(short, long, required = true)]
     riot_ids: Vec<String>,
+    /// Saved match JSON files or directories of them, used instead of the Riot API
+    #[arg(long)]
+    match_files: Vec<PathBuf>,
     #[arg(long, default_value = "false")]
     remove_matches: bool,
     #[arg(long, default_value = "false")]
     force: bool,
+    #[command(flatten)]
+    filters: FilterArgs,
+    /// Only discover matches and build events, then print the clip plan as JSON
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum Command {
+    /// Processes the recent archive VODs of every streamer in a profiles file
+    Batch {
+        /// JSON array of profiles with `channel`, `riot_ids` and optional `category`,
+        /// `exclude_category`, `only_customs`, `lookback_hours`, `output_dir` and `hud_profile`
+        #[arg(short, long)]
+        profiles: PathBuf,
+        #[arg(long, default_value = "false")]
+        remove_matches: bool,
+        #[arg(long, default_value = "false")]
+        force: bool,
+        /// Only discover matches and build events, then print the clip plans as JSON
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Discovers the matches of a VOD and writes the match list
+    Discover {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(short, long, required = true)]
+        riot_ids: Vec<String>,
+        /// Saved match JSON files or directories of them, used instead of the Riot API
+        #[arg(long)]
+        match_files: Vec<PathBuf>,
+        #[command(flatten)]
+        filters: FilterArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Downloads the match videos of the match list
+    Download(StageArgs),
+    /// Runs the kill detector on the match videos and writes the detection timelines
+    Detect(StageArgs),
+    /// Aligns the detection timelines with the matches and writes the offsets
+    Align(StageArgs),
+    /// Builds the events with the written offsets and writes the planned clips
+    Events {
+        #[command(flatten)]
+        stage: StageArgs,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Cuts the planned clips that don't exist yet
+    Cut(StageArgs),
+    /// Cuts all planned clips again
+    Recut(StageArgs),
+    /// Shows, sets or clears the stored offset of a match
+    Offset(OffsetArgs),
+    /// Writes alignment fixtures of matches from their detection timelines, taking their
+    /// manual offsets as correct
+    Fixture {
+        #[command(flatten)]
+        stage: StageArgs,
+        #[arg(long, default_value = "fixtures/alignment")]
+        fixtures: PathBuf,
+    },
+    /// Exports the kill banner area at the kills of aligned matches and of stretches without
+    /// kills as training data for the kill detector, frames where the detector sees a banner
+    /// without a kill go to `hard_no_kill`
+    TrainingData {
+        #[command(flatten)]
+        stage: StageArgs,
+        #[arg(long, default_value = "kill-data")]
+        output: PathBuf,
+    },
+    /// Measures the lag of each detection model per HUD profile on the matches with a manual
+    /// offset, later alignments remove it from the detected anchors
+    Calibrate {
+        #[command(flatten)]
+        sources: SourcesArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Measures the accuracy and runtime of the offset search on generated cases and fixtures
+    Bench {
+        /// Number of generated cases
+        #[arg(long, default_value = "200")]
+        cases: usize,
+        #[arg(long, default_value = "1")]
+        seed: u64,
+        /// JSON file with the noise, miss rate, false positives, gap and drift of the
+        /// generated cases
+        #[arg(long)]
+        scenario: Option<PathBuf>,
+        /// Directory of JSON alignment cases
+        #[arg(long, default_value = "fixtures/alignment")]
+        fixtures: PathBuf,
+        /// Also print the result of every case
+        #[arg(long, default_value = "false")]
+        verbose: bool,
+    },
+}
+
+/// The VOD and matches a single stage runs for.
+#[derive(Args, Debug, Clone, PartialEq)]
+struct StageArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    /// Defaults to all matches of the match list
+    #[arg(short, long)]
+    match_id: Option<Uuid>,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+struct OffsetArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    #[arg(short, long)]
+    match_id: Uuid,
+    /// Position of game time zero in the match video in milliseconds, negative if the video
+    /// misses the start of the game. Later runs use it instead of detecting the offset and
+    /// try the match again if it failed
+    #[arg(long, allow_negative_numbers = true, conflicts_with = "clear")]
+    set: Option<i64>,
+    /// Removes the stored offset so the next run detects it again
+    #[arg(long, default_value = "false")]
+    clear: bool,
+    /// Plans and cuts the clips of the match again with the stored offset, needs the match
+    /// list written by `discover`
+    #[arg(long, default_value = "false", conflicts_with = "clear")]
+    recut: bool,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+struct SourcesArgs {
+    #[arg(short, long, required_unless_present = "local_files")]
+    vod_ids: Vec<String>,
+    /// Local recordings to use besides Twitch VODs
+    #[arg(short, long)]
+    local_files: Vec<PathBuf>,
+    /// RFC 3339 start time of the recording at the same position in `--local-files`.
+    /// Defaults to the `creation_time` metadata or the file creation time.
+    #[arg(long)]
+    recording_start: Vec<String>,
+}
+
+impl SourcesArgs {
+    fn sources(self) -> Result<Vec<VodSource>, Error> {
+        let vods = self
+            .vod_ids
+            .iter()
+            .flat_map(|x| x.split(','))
+            .flat_map(|x| x.split(' '))
+            .flat_map(|x| x.split('\n'))
+            .map(|x| {
+                x.parse()
+                    .map(VodSource::Twitch)
+                    .map_err(|e| Error::Config(format!("Invalid VOD ID {x}: {e}")))
+            });
+        let local_files = self.local_files.into_iter().enumerate().map(|(i, path)| {
+            let start = self
+                .recording_start
+                .get(i)
+                .map(|s| parse_recording_start(s));
+            Ok(VodSource::Local {
+                path,
+                start: start.transpose()?,
+            })
+        });
+        vods.chain(local_files).collect()
+    }
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+struct SourceArgs {
+    #[arg(short, long, required_unless_present = "local_file")]
+    vod_id: Option<usize>,
+    /// Local recording to use instead of a Twitch VOD
+    #[arg(short, long, conflicts_with = "vod_id")]
+    local_file: Option<PathBuf>,
+    /// RFC 3339 start time of the local recording
+    #[arg(long, requires = "local_file")]
+    recording_start: Option<String>,
+}
+
+impl SourceArgs {
+    fn source(self) -> Result<VodSource, Error> {
+        Ok(match (self.vod_id, self.local_file) {
+            (Some(vod_id), _) => VodSource::Twitch(vod_id),
+            (None, Some(path)) => VodSource::Local {
+                path,
+                start: self
+                    .recording_start
+                    .as_deref()
+                    .map(parse_recording_start)
+                    .transpose()?,
+            },
+            (None, None) => unreachable!("clap requires a VOD or a local file"),
+        })
+    }
+}
+
+fn parse_recording_start(start: &str) -> Result<OffsetDateTime, Error> {
+    OffsetDateTime::parse(start, &Rfc3339)
+        .map_err(|e| Error::Config(format!("Invalid recording start {start}: {e}")))
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+struct FilterArgs {
     #[arg(long)]
     category: Option<Vec<String>>,
     #[arg(long)]
@@ -55,287 +256,376 @@ struct Cli {
     matches_before: u64,
 }
 
+impl From<FilterArgs> for Filters {
+    fn from(args: FilterArgs) -> Self {
+        Filters {
+            category: args.category,
+            exclude_category: args.exclude_category,
+            only_customs: args.only_customs,
+            matches_after: args.matches_after,
+            matches_before: args.matches_before,
+        }
+    }
+}
+
+/// Overrides of the [`Config`] values.
+#[derive(Args, Debug, Clone, PartialEq)]
+struct ConfigArgs {
+    /// JSON config file, defaults to `$CLIP_CUTTER_CONFIG`
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    #[arg(long)]
+    scratch_dir: Option<PathBuf>,
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    #[arg(long)]
+    model: Option<PathBuf>,
+    /// Detector the models are run with: `onnx` or `template`
+    #[arg(long)]
+    detector: Option<DetectorBackend>,
+    /// HUD profile of the match videos, `auto` to probe each video
+    #[arg(long)]
+    hud_profile: Option<String>,
+    /// VODs processed at the same time
+    #[arg(long)]
+    max_vods: Option<usize>,
+    /// Matches of one VOD processed at the same time
+    #[arg(long)]
+    max_matches: Option<usize>,
+    /// Match videos downloaded at the same time
+    #[arg(long)]
+    max_downloads: Option<usize>,
+    /// Match videos run through the kill detector at the same time
+    #[arg(long)]
+    max_inference: Option<usize>,
+    /// Clips cut at the same time
+    #[arg(long)]
+    max_cuts: Option<usize>,
+    /// Matches whose alignment confidence is below this are not cut
+    #[arg(long)]
+    min_confidence: Option<f64>,
+    /// Frame rate of the detection scan over the whole match video
+    #[arg(long)]
+    coarse_rate: Option<f32>,
+    /// Frame rate of a second detection pass around the frames the scan found anchors in
+    #[arg(long)]
+    fine_rate: Option<f32>,
+    /// Stop detecting an anchor once as many events as the match has are found
+    #[arg(long, default_value = "false")]
+    early_stop: bool,
+    /// Snap clips starting with a kill to the kill banner detected at a higher frame rate
+    #[arg(long, default_value = "false")]
+    refine_events: bool,
+}
+
+impl ConfigArgs {
+    fn load(self) -> Result<Config, Error> {
+        let mut config = Config::load(self.config.as_deref())?;
+        for (arg, value) in [
+            (self.state_dir, &mut config.state_dir),
+            (self.cache_dir, &mut config.cache_dir),
+            (self.scratch_dir, &mut config.scratch_dir),
+            (self.output_dir, &mut config.output_dir),
+            (self.model, &mut config.model_path),
+        ] {
+            if let Some(arg) = arg {
+                *value = arg;
+            }
+        }
+        for (arg, value) in [
+            (self.max_vods, &mut config.concurrency.vods),
+            (self.max_matches, &mut config.concurrency.matches),
+            (self.max_downloads, &mut config.concurrency.downloads),
+            (self.max_inference, &mut config.concurrency.inference),
+            (self.max_cuts, &mut config.concurrency.cuts),
+        ] {
+            if let Some(arg) = arg {
+                *value = arg;
+            }
+        }
+        if let Some(detector) = self.detector {
+            config.detector = detector;
+        }
+        if let Some(hud_profile) = self.hud_profile {
+            config.hud_profile = hud_profile;
+        }
+        if let Some(min_confidence) = self.min_confidence {
+            config.min_confidence = min_confidence;
+        }
+        if let Some(coarse_rate) = self.coarse_rate {
+            config.sampling.coarse_rate = coarse_rate;
+        }
+        if self.fine_rate.is_some() {
+            config.sampling.fine_rate = self.fine_rate;
+        }
+        config.early_stop |= self.early_stop;
+        config.refine_events |= self.refine_events;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 #[tokio::main]
 pub async fn main() {
     dotenv().ok();
     env_logger::init();
     ffmpeg_sidecar::download::auto_download().expect("Failed to download ffmpeg");
-    let args = Cli::parse();
+    if let Err(e) = run(Cli::parse()).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-    let puuids = futures::future::join_all(
-        args.riot_ids
-            .iter()
-            .flat_map(|riot_id| riot_id.split(','))
-            .map(valorant::get_puuid),
-    )
-    .await
-    .into_iter()
-    .filter_map(|x| x.ok())
-    .collect::<HashSet<_>>();
+async fn run(args: Cli) -> Result<(), Error> {
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
 
-    let vod_ids = args
-        .vod_ids
-        .iter()
-        .flat_map(|x| x.split(','))
-        .flat_map(|x| x.split(' '))
-        .flat_map(|x| x.split('\n'))
-        .collect_vec();
+    let config = args.config.load()?;
 
-    for vod_id in vod_ids {
-        process_vod(vod_id.parse().unwrap(), &puuids, args.clone()).await;
-    }
-}
+    let pipeline = Pipeline::builder()
+        .sources(args.sources.sources()?)
+        .riot_ids(args.riot_ids.iter().flat_map(|riot_id| riot_id.split(',')))
+        .match_files(args.match_files)
+        .filters(args.filters.into())
+        .force(args.force)
+        .remove_matches(args.remove_matches)
+        .config(config)
+        .build();
 
-async fn process_vod(vod_id: usize, puuids: &HashSet<String>, args: Cli) {
-    let vod_interval = twitch::get_vod_start_end(vod_id).await;
-    let matches =
-        valorant::find_valorant_matches_by_players(puuids, vod_interval, vod_id, args.force)
-            .await
-            .expect("Failed to find matches");
+    execute(&pipeline, args.dry_run).await
+}
 
-    for valo_match in matches {
-        if args.only_customs && valo_match.match_info.provisioning_flow_id != "CustomGame" {
-            debug!(
-                "Skipping match: {:?} not a custom game",
-                valo_match.match_info.match_id
-            );
-            continue;
+async fn run_command(command: Command) -> Result<(), Error> {
+    match command {
+        Command::Batch {
+            profiles,
+            remove_matches,
+            force,
+            dry_run,
+            config,
+        } => {
+            let config = config.load()?;
+            let profiles = batch::load_profiles(&profiles)?;
+            for profile in profiles {
+                info!("Processing {}", profile.channel);
+                let vods = match profile.recent_vods().await {
+                    Ok(vods) => vods,
+                    Err(e) => {
+                        error!("Failed to list VODs of {}: {}", profile.channel, e);
+                        continue;
+                    }
+                };
+                let pipeline = profile
+                    .pipeline(&config)
+                    .vods(vods)
+                    .force(force)
+                    .remove_matches(remove_matches)
+                    .build();
+                execute(&pipeline, dry_run).await?;
+            }
+            Ok(())
         }
-        if args.only_customs && valo_match.players.len() < 10 {
-            debug!(
-                "Skipping match: {:?} not enough players",
-                valo_match.match_info.match_id
-            );
-            continue;
+        Command::Discover {
+            source,
+            riot_ids,
+            match_files,
+            filters,
+            config,
+        } => {
+            let pipeline = Pipeline::builder()
+                .riot_ids(riot_ids.iter().flat_map(|riot_id| riot_id.split(',')))
+                .match_files(match_files)
+                .filters(filters.into())
+                .config(config.load()?)
+                .build();
+            let match_list = stages::discover(&pipeline, &source.source()?).await?;
+            print_json(&match_list.match_ids());
+            Ok(())
         }
-        if valo_match.match_info.game_start_millis < args.matches_after {
-            debug!(
-                "Skipping match: {:?} before matches_after",
-                valo_match.match_info.match_id
-            );
-            continue;
+        Command::Download(args) => run_stage(args, None, Stage::Download).await,
+        Command::Detect(args) => run_stage(args, None, Stage::Detect).await,
+        Command::Align(args) => run_stage(args, None, Stage::Align).await,
+        Command::Events { stage, filters } => {
+            run_stage(stage, Some(filters.into()), Stage::Events).await
         }
-        if valo_match.match_info.game_start_millis > args.matches_before {
-            debug!(
-                "Skipping match: {:?} before matches_before",
-                valo_match.match_info.match_id
-            );
-            continue;
+        Command::Cut(args) => run_stage(args, None, Stage::Cut).await,
+        Command::Recut(args) => run_stage(args, None, Stage::Recut).await,
+        Command::Offset(args) => set_offset(args).await,
+        Command::Fixture { stage, fixtures } => {
+            run_stage(stage, None, Stage::Fixture(fixtures)).await
         }
-        let match_id = valo_match.match_info.match_id;
-
-        let processed_path = Path::new("/processed").join(format!("{vod_id}-{match_id}"));
-        let failed_path = Path::new("/failed").join(format!("{vod_id}-{match_id}"));
-        if process_match(
-            puuids,
-            vod_id,
-            vod_interval,
-            &valo_match,
-            args.remove_matches,
-            &args.category,
-            &args.exclude_category,
-        )
-        .await
-        .is_some()
-        {
-            std::fs::create_dir_all(processed_path.parent().unwrap()).ok();
-            std::fs::write(processed_path, "").unwrap();
-        } else {
-            std::fs::create_dir_all(failed_path.parent().unwrap()).ok();
-            std::fs::write(failed_path, "").unwrap();
+        Command::TrainingData { stage, output } => {
+            run_stage(stage, None, Stage::TrainingData(output)).await
+        }
+        Command::Calibrate { sources, config } => calibrate(config, &sources.sources()?),
+        Command::Bench {
+            cases,
+            seed,
+            scenario,
+            fixtures,
+            verbose,
+        } => {
+            let scenario: bench::Scenario = match scenario {
+                Some(path) => {
+                    let content = std::fs::read_to_string(&path).map_err(|e| {
+                        Error::Config(format!("Failed to read {}: {e}", path.display()))
+                    })?;
+                    serde_json::from_str(&content).map_err(|e| {
+                        Error::Config(format!("Failed to parse {}: {e}", path.display()))
+                    })?
+                }
+                None => bench::Scenario::default(),
+            };
+            let fixtures = bench::load_fixtures(&fixtures)?;
+            print_json(&serde_json::json!({
+                "generated": bench::run(&scenario.generate(cases, seed), verbose),
+                "fixtures": bench::run(&fixtures, verbose),
+            }));
+            Ok(())
         }
     }
 }
 
-async fn process_match(
-    puuids: &HashSet<String>,
-    vod_id: usize,
-    vod_interval: (OffsetDateTime, OffsetDateTime),
-    valo_match: &MatchDetailsV1,
-    remove_matches: bool,
-    category: &Option<Vec<String>>,
-    exclude_category: &Option<Vec<String>>,
-) -> Option<()> {
-    debug!(
-        "Filtering for category: {:?} excluding category: {:?}",
-        category, exclude_category
-    );
-    let events = events::build_events(valo_match)
-        .into_iter()
-        .filter(|e| match e {
-            Event::Kill(e) => e.is_from_puuids(puuids) || e.is_against_puuids(puuids),
-            Event::MultiKill(e) => e.is_from_puuids(puuids),
-            Event::Clutch(e) => e.is_from_puuids(puuids),
-            Event::DoubleKill(e) => e.is_from_puuids(puuids),
-            Event::Plant(e) => e.is_from_puuids(puuids),
-            Event::Defuse(e) => e.is_from_puuids(puuids),
-            Event::Ace(e) => e.is_from_puuids(puuids),
-            Event::Retake(e) => e.is_from_puuids(puuids),
-        })
-        .collect_vec();
-
-    let mut filtered_events = vec![];
-    for event in events {
-        if let Some(exclude_category) = exclude_category.as_ref() {
-            if exclude_category.contains(&event.category(puuids).await) {
-                continue;
-            }
-        }
-        if let Some(category) = category.as_ref() {
-            if category.contains(&event.category(puuids).await) {
-                filtered_events.push(event);
-            }
-        } else {
-            filtered_events.push(event);
-        }
+/// Applies the offset changes of `args`, prints the stored alignment and recuts if asked to.
+async fn set_offset(args: OffsetArgs) -> Result<(), Error> {
+    let pipeline = Pipeline::builder().config(args.config.load()?).build();
+    let source = args.source.source()?;
+    let vod_id = source.id();
+    if args.clear {
+        pipeline
+            .state()
+            .remove_alignment(&vod_id, args.match_id)
+            .expect("Failed to remove alignment");
     }
-    let events = filtered_events;
-
-    info!("Found {} events", events.len());
-
-    if events.is_empty() {
-        return None;
+    if let Some(offset) = args.set {
+        pipeline.set_manual_offset(&vod_id, args.match_id, offset)?;
     }
-
-    let match_kill_events = valorant::get_match_kills(valo_match)
-        .iter()
-        .filter(|k| puuids.contains(&k.killer))
-        .map(|v| v.time_since_game_start_millis)
-        .map(Duration::from_millis)
-        .sorted()
-        .collect::<Vec<_>>();
-
-    if match_kill_events.is_empty() {
-        error!("No match kill events found");
-        return None;
+    print_json(&pipeline.state().load_alignment(&vod_id, args.match_id));
+    if args.recut {
+        match stages::replan(&pipeline, &source, args.match_id).await {
+            Ok(clips) => print_json(&clips),
+            Err(e) => error!("Failed to recut match {}: {}", args.match_id, e),
+        }
     }
+    Ok(())
+}
 
-    let match_video_path =
-        Path::new("matches").join(format!("{}-{}.mkv", vod_id, valo_match.match_info.match_id));
-    let (start, end) = match video::get_match_interval(vod_interval.0, valo_match) {
-        Ok(interval) => interval,
-        Err(msg) => {
-            error!("Failed to get match interval: {}", msg);
-            return None;
+/// Calibrates every detection model on every HUD profile with samples from `sources`, then
+/// stores and prints the calibrations.
+fn calibrate(config: ConfigArgs, sources: &[VodSource]) -> Result<(), Error> {
+    let pipeline = Pipeline::builder().config(config.load()?).build();
+    let mut samples = BTreeMap::<(String, String), Vec<i64>>::new();
+    for source in sources {
+        match stages::calibration_samples(&pipeline, source) {
+            Ok(source_samples) => {
+                for (key, lags) in source_samples {
+                    samples.entry(key).or_default().extend(lags);
+                }
+            }
+            Err(e) => error!("Failed to measure VOD {}: {}", source.id(), e),
         }
-    };
-    valorant::save_match_video(&match_video_path, vod_id, start, end)
-        .expect("Failed to save video");
-
-    let min_offset = valo_match.match_info.queue_id.map_or(40000, |q| match q {
-        Queue::Deathmatch => 0,
-        Queue::Competitive => 60000,
-        _ => 40000,
-    });
-
-    let kill_timestamps = video::detect_kill_timestamps(&match_video_path, min_offset);
-    let detected_kill_events = video::detect_kill_events(min_offset, 0, &kill_timestamps)
-        .into_iter()
-        .sorted()
-        .collect::<Vec<_>>();
-
-    if detected_kill_events.is_empty() {
-        error!("No detected kill events found");
-        return None;
     }
-
-    if (detected_kill_events.len() as i64) < (match_kill_events.len() as i64 / 2) {
-        error!("Fewer detected kill events than match kill events: Detected Kills: {}, Match Kills: {}", detected_kill_events.len(), match_kill_events.len());
-        return None;
+    let mut calibrations = vec![];
+    for ((model_hash, hud_profile), lags) in samples {
+        let Some(calibration) = Calibration::new(&model_hash, &hud_profile, &lags) else {
+            continue;
+        };
+        pipeline
+            .state()
+            .save_calibration(&calibration)
+            .expect("Failed to save calibration");
+        calibrations.push(calibration);
     }
+    if calibrations.is_empty() {
+        error!("No detected anchors near the anchors of manually aligned matches");
+    }
+    print_json(&calibrations);
+    Ok(())
+}
 
-    let offset = match offset::get_offset(&detected_kill_events, &match_kill_events, min_offset) {
-        Some(offset) => offset,
-        None => {
-            let detected_kill_events = video::detect_kill_events(min_offset, 1, &kill_timestamps)
-                .into_iter()
-                .sorted()
-                .collect::<Vec<_>>();
+enum Stage {
+    Download,
+    Detect,
+    Align,
+    Events,
+    Cut,
+    Recut,
+    Fixture(PathBuf),
+    TrainingData(PathBuf),
+}
 
-            if detected_kill_events.is_empty() {
-                error!("No detected kill events found");
-                return None;
+/// Runs `stage` for the matches of `args` and prints the results as JSON.
+async fn run_stage(args: StageArgs, filters: Option<Filters>, stage: Stage) -> Result<(), Error> {
+    let pipeline = Pipeline::builder()
+        .filters(filters.unwrap_or_default())
+        .config(args.config.load()?)
+        .build();
+    let source = args.source.source()?;
+    let match_ids = match args.match_id {
+        Some(match_id) => vec![match_id],
+        None => stages::match_list(&pipeline, &source)?.match_ids(),
+    };
+    for match_id in match_ids {
+        let result: Result<serde_json::Value, Error> = match stage {
+            Stage::Download => stages::download(&pipeline, &source, match_id)
+                .await
+                .map(|path| serde_json::json!(path)),
+            Stage::Detect => stages::detect(&pipeline, &source, match_id)
+                .await
+                .map(|timeline| serde_json::json!(timeline.frames.len())),
+            Stage::Align => stages::align(&pipeline, &source, match_id)
+                .map(|alignment| serde_json::json!(alignment)),
+            Stage::Events => stages::events(&pipeline, &source, match_id)
+                .await
+                .map(|manifest| serde_json::json!(manifest)),
+            Stage::Fixture(ref dir) => stages::fixture(&pipeline, &source, match_id, dir)
+                .map(|path| serde_json::json!(path)),
+            Stage::TrainingData(ref dir) => {
+                stages::export_training_data(&pipeline, &source, match_id, dir)
+                    .await
+                    .map(|export| serde_json::json!(export))
             }
-
-            if (detected_kill_events.len() as i64) < (match_kill_events.len() as i64 / 2) {
-                error!("Fewer detected kill events than match kill events: Detected Kills: {}, Match Kills: {}", detected_kill_events.len(), match_kill_events.len());
-                return None;
+            Stage::Cut | Stage::Recut => {
+                stages::cut(&pipeline, &source, match_id, matches!(stage, Stage::Recut))
+                    .await
+                    .map(|clips| serde_json::json!(clips))
             }
-            offset::get_offset(&detected_kill_events, &match_kill_events, min_offset)?
-        }
-    };
-
-    let offset = Duration::from_millis(offset - 350);
-
-    let match_date =
-        OffsetDateTime::from_unix_timestamp(valo_match.match_info.game_start_millis as i64 / 1000)
-            .ok()?;
-    let map_name = valorant::get_map_name(&valo_match.match_info.map_id)
-        .await
-        .expect("Failed to get map name")
-        .expect("Failed to get map name");
-    let game_mode = &valo_match
-        .match_info
-        .queue_id
-        .map_or("other".to_string(), |q| q.to_string());
-
-    for event in tqdm!(events.iter(), desc = "Saving clips", total = events.len()) {
-        let category = event.category(puuids).await;
-        let name_postfix = event
-            .name_postfix(valo_match)
-            .await
-            .replace([' ', '/'], "_");
-
-        let (start, end) = event.game_time_interval();
-        let event_date = (match_date + start).format(&CLIP_DATE_TIME_PREFIX).unwrap();
-        let (start, end) = (start + offset, end + offset);
-
-        // timestamp of event in vod
-        let event_vod_time = match_date - vod_interval.0 + start;
-        let seconds = event_vod_time.whole_seconds();
-        let hours = seconds / 3600;
-        let minutes = (seconds % 3600) / 60;
-        let seconds = seconds % 60;
-        let event_vod_time = format!("{hours:02}-{minutes:02}-{seconds:02}");
-
-        let clip_name = format!("{event_vod_time}_{event_date}_{map_name}_{name_postfix}.mp4");
-        let clip_path = Path::new("clips")
-            .join(format!(
-                "{}_{}",
-                vod_id,
-                vod_interval.0.format(&CLIP_DATE_PREFIX).unwrap()
-            ))
-            .join(game_mode)
-            .join(&category)
-            .join(clip_name);
-        std::fs::create_dir_all(clip_path.parent()?).ok()?;
-
-        let (start, end) = (start - CLIP_PADDING.0, end + CLIP_PADDING.1);
-        let metadata = Metadata {
-            track: offset.as_millis().to_string(),
-            title: category.to_string(),
-            album: valo_match.match_info.match_id.to_string(),
-            episode_id: valo_match.match_info.season_id.to_string(),
-            description: serde_json::to_string(&event).unwrap(),
-            genre: game_mode.to_string(),
         };
-        if let Err(e) = video::split_video(
-            &match_video_path,
-            &clip_path,
-            start,
-            end,
-            true,
-            Some(metadata),
-        ) {
-            error!("Failed to save clip: {}", e)
-        } else {
-            let file_time = FileTime::from_system_time(SystemTime::from(match_date + start));
-            set_file_times(&clip_path, file_time, file_time, file_time).ok()?;
+        match result {
+            Ok(result) => {
+                print_json(&serde_json::json!({ "match_id": match_id, "result": result }))
+            }
+            Err(e) => error!("Failed to process match {}: {}", match_id, e),
         }
     }
+    Ok(())
+}
 
-    if remove_matches {
-        std::fs::remove_file(match_video_path).ok();
+fn print_json<T: serde::Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// Prints the plan of `pipeline` if `dry_run`, otherwise runs it and logs the failures.
+async fn execute(pipeline: &Pipeline, dry_run: bool) -> Result<(), Error> {
+    if dry_run {
+        print_json(&pipeline.plan().await?);
+    } else {
+        let results = pipeline.run().await?;
+        let failed = results
+            .iter()
+            .map(|vod| match &vod.matches {
+                Ok(matches) => matches.iter().filter(|m| m.clips.is_err()).count(),
+                Err(_) => 1,
+            })
+            .sum::<usize>();
+        if failed > 0 {
+            error!("{} VODs or matches failed", failed);
+        }
     }
-    Some(())
+    Ok(())
 }
@@ -0,0 +1,113 @@
+use crate::events::Event;
+use crate::offset::Alignment;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The clips planned for a match and how far cutting them got.
+///
+/// Reruns compare their plan against the previous manifest so only missing clips are cut.
+/// Clips of earlier runs that no longer match the plan are kept with [`ClipStatus::Stale`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub vod_id: String,
+    pub match_id: Uuid,
+    /// Alignment of game time and match video time used for the clips.
+    pub alignment: Alignment,
+    /// How much of the match is missing at the start of the match video.
+    #[serde(default)]
+    pub missing_start_millis: u64,
+    pub clips: Vec<ManifestClip>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestClip {
+    /// Hash of the event and the cut settings, see [`event_hash`].
+    pub event_hash: String,
+    pub category: String,
+    pub path: PathBuf,
+    pub offset_millis: i64,
+    /// The offset the clip was cut with after refining it around the event, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refined_offset_millis: Option<i64>,
+    pub status: ClipStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipStatus {
+    Planned,
+    Done,
+    Failed,
+    /// Written by an earlier run with an event, path or offset that is no longer planned.
+    Stale,
+    /// The event is not in the match video because the VOD started late or ended early.
+    OutsideVideo,
+}
+
+impl ManifestClip {
+    fn same_clip(&self, other: &ManifestClip) -> bool {
+        self.event_hash == other.event_hash
+            && self.path == other.path
+            && self.offset_millis == other.offset_millis
+    }
+}
+
+impl Manifest {
+    /// Takes over the done clips of `previous` that are still planned and whose files exist,
+    /// and marks the other done clips of `previous` as stale.
+    pub fn with_previous(mut self, previous: Option<&Manifest>) -> Self {
+        let Some(previous) = previous else {
+            return self;
+        };
+        for clip in &mut self.clips {
+            if let Some(old) = previous.clips.iter().find(|old| {
+                old.status == ClipStatus::Done && old.same_clip(clip) && old.path.exists()
+            }) {
+                clip.status = ClipStatus::Done;
+                clip.refined_offset_millis = old.refined_offset_millis;
+            }
+        }
+        let stale = previous
+            .clips
+            .iter()
+            .filter(|old| matches!(old.status, ClipStatus::Done | ClipStatus::Stale))
+            .filter(|old| !self.clips.iter().any(|clip| clip.same_clip(old)))
+            .map(|old| ManifestClip {
+                status: ClipStatus::Stale,
+                ..old.clone()
+            })
+            .collect::<Vec<_>>();
+        self.clips.extend(stale);
+        self
+    }
+
+    /// Whether all planned clips are done or can't be cut.
+    pub fn is_complete(&self) -> bool {
+        self.clips.iter().all(|clip| {
+            matches!(
+                clip.status,
+                ClipStatus::Done | ClipStatus::Stale | ClipStatus::OutsideVideo
+            )
+        })
+    }
+}
+
+/// A stable hash of `event` and the padding around it, so a clip is recut when either changes.
+pub fn event_hash(event: &Event, padding: (Duration, Duration)) -> String {
+    let key = format!(
+        "{}:{}:{}",
+        serde_json::to_string(event).unwrap(),
+        padding.0.as_millis(),
+        padding.1.as_millis()
+    );
+    format!("{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// FNV-1a, std's hashers are not guaranteed to be stable between releases.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,137 +1,744 @@
-use itertools::Itertools;
-use kdam::tqdm;
 use log::debug;
-use rand::prelude::StdRng;
-use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
 use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-pub(crate) fn get_offset(
-    offsets1: &[Duration],
-    offsets2: &[Duration],
-    min_offset: u64,
-) -> Option<u64> {
-    debug!("Finding best offset from {:?} to {:?}", offsets1, offsets2);
-    let offsets1 = offsets1
-        .iter()
-        .map(|x| x.as_millis() as u32)
-        .collect::<Vec<_>>();
-    let offsets2 = offsets2
+/// Largest offset between game time and the start of a complete match video.
+pub const MAX_OFFSET_MILLIS: i64 = 250000;
+/// How far a detected anchor may be from a shifted match anchor to count as the same event.
+pub const INLIER_TOLERANCE_MILLIS: i64 = 500;
+const REFINEMENT_ROUNDS: usize = 5;
+/// Alignments with fewer inliers get a proportionally lower confidence. Also the least number
+/// of anchors a piece of a piecewise alignment needs.
+const MIN_INLIERS: usize = 4;
+/// Most pieces a piecewise alignment is split into.
+const MAX_PIECES: usize = 8;
+/// Largest change of the offset from the first found piece to another piece, e.g. by a gap in
+/// the VOD. Other pieces may leave the searched offsets by up to this much.
+const MAX_GAP_MILLIS: i64 = 5 * 60 * 1000;
+/// Pairs of the segments found so far that a new segment may take the game time or the detected
+/// anchor of. A single coincidental inlier on the wrong side of a gap would otherwise hide the
+/// piece after it or move its start.
+const MAX_STRAYS: usize = 1;
+
+/// The mapping from game time to match video time and how well it explains the anchors.
+///
+/// Without `pieces` the mapping is the constant `offset_millis`. Otherwise the video is
+/// assumed to have gaps, restarts or drift and each piece maps the game time from its start
+/// to the start of the next piece.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alignment {
+    /// Position of game time zero in the match video, for the constant mapping.
+    pub offset_millis: i64,
+    /// Number of match anchors with a detected anchor within the inlier tolerance.
+    pub inliers: usize,
+    /// Mean absolute distance between the inliers and their detected anchors.
+    pub residual_ms: f64,
+    /// Between 0 and 1, see [`align`].
+    pub confidence: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pieces: Vec<WarpPiece>,
+}
+
+/// A part of a piecewise alignment, its offset changes by `drift` per game millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WarpPiece {
+    pub from_game_millis: u64,
+    pub offset_millis: i64,
+    pub drift: f64,
+}
+
+/// How an alignment was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignmentMethod {
+    /// By [`align`] from the detected anchors.
+    Detected,
+    /// Set by hand, takes precedence over detection.
+    Manual,
+}
+
+/// The alignment of a match in a VOD, stored so later runs don't detect it again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlignmentRecord {
+    pub vod_id: String,
+    pub match_id: Uuid,
+    pub method: AlignmentMethod,
+    pub alignment: Alignment,
+    /// RFC 3339 time the record was created.
+    pub created_at: String,
+}
+
+impl AlignmentRecord {
+    pub fn new(
+        vod_id: &str,
+        match_id: Uuid,
+        method: AlignmentMethod,
+        alignment: Alignment,
+    ) -> Self {
+        Self {
+            vod_id: vod_id.to_string(),
+            match_id,
+            method,
+            alignment,
+            created_at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+        }
+    }
+}
+
+impl Alignment {
+    /// A constant offset set by hand, trusted fully.
+    pub fn manual(offset_millis: i64) -> Self {
+        Self {
+            offset_millis,
+            inliers: 0,
+            residual_ms: 0.,
+            confidence: 1.,
+            pieces: vec![],
+        }
+    }
+
+    /// The offset between game time and video time at `game_millis`.
+    pub fn offset_at(&self, game_millis: u64) -> i64 {
+        match self
+            .pieces
+            .iter()
+            .rev()
+            .find(|piece| piece.from_game_millis <= game_millis)
+            .or(self.pieces.first())
+        {
+            Some(piece) => {
+                let elapsed = game_millis as f64 - piece.from_game_millis as f64;
+                piece.offset_millis + (piece.drift * elapsed).round() as i64
+            }
+            None => self.offset_millis,
+        }
+    }
+
+    /// Maps a game time to the match video, `None` if it is before the video start.
+    pub fn map(&self, game_time: Duration) -> Option<Duration> {
+        let game_millis = game_time.as_millis() as u64;
+        let offset = self.offset_at(game_millis);
+        u64::try_from(game_millis as i64 + offset)
+            .ok()
+            .map(Duration::from_millis)
+    }
+
+    /// Moves the whole mapping by `millis`.
+    pub fn shifted(mut self, millis: i64) -> Self {
+        self.offset_millis += millis;
+        for piece in &mut self.pieces {
+            piece.offset_millis += millis;
+        }
+        self
+    }
+}
+
+/// Detected and match times of one kind of anchor, e.g. kills or spike plants. Only anchors
+/// of the same track are paired.
+#[derive(Debug, Clone, Copy)]
+pub struct Track<'a> {
+    pub detected: &'a [Duration],
+    pub in_match: &'a [Duration],
+}
+
+/// Finds the mapping that puts the most match anchors onto detected anchors of the same
+/// track, considering only `offsets`. Offsets are negative if the match video misses the
+/// start of the game.
+///
+/// Every pair of detected and match anchor votes for its difference. The offset with the most
+/// votes within [`INLIER_TOLERANCE_MILLIS`] is refined to the median difference of its
+/// inliers. The anchors that don't fit are voted on again to find pieces of the match with
+/// another offset, e.g. after a gap in the VOD. Neighbouring pieces that fit a common line
+/// are merged into one drifting piece. A single piece without drift is reported as the
+/// constant offset.
+///
+/// The confidence is the product of the share of anchors that are inliers, whether there are
+/// at least [`MIN_INLIERS`], how much the best offset is ahead of the best clearly different
+/// offset, and how small the residual is compared to the tolerance.
+pub fn align(tracks: &[Track], offsets: Range<i64>) -> Option<Alignment> {
+    debug!("Aligning {:?}", tracks);
+    let tracks = tracks
         .iter()
-        .map(|x| x.as_millis() as u32)
+        .map(|track| Anchors {
+            detected: to_millis(track.detected),
+            in_match: to_millis(track.in_match),
+        })
         .collect::<Vec<_>>();
 
-    find_best_offset_semi_statistically(&offsets1, &offsets2, min_offset)
-}
+    let votes = votes(&tracks, &offsets);
+    let (best, best_votes) = densest_window(&votes, &[])?;
+    let first = Segment::refine(best, &tracks)?;
+    let offset_millis = first.offset;
+    let segments = find_segments(first, &tracks, &offsets);
+
+    // the other pieces of the match don't compete with the best offset
+    let found = std::iter::once(best)
+        .chain(segments.iter().map(|s| s.offset))
+        .map(|offset| (offset, 4 * INLIER_TOLERANCE_MILLIS))
+        .collect::<Vec<_>>();
+    let (_, runner_up_votes) = densest_window(&votes, &found).unwrap_or((0, 0));
+    let runner_up_share = runner_up_votes as f64 / best_votes.max(runner_up_votes) as f64;
+    let uniqueness = 1. - runner_up_share.powi(2);
+    let segments = merge_drifting(segments);
 
-fn find_best_offset_semi_statistically(
-    offsets1: &[u32],
-    offsets2: &[u32],
-    min_offset: u64,
-) -> Option<u64> {
-    let mut removers = vec![];
-    let max = if offsets1.len() > offsets2.len() {
-        offsets1.len() - offsets2.len()
+    let pieces = if segments.len() == 1 && segments[0].drift == 0. {
+        vec![]
     } else {
-        2
+        to_pieces(&segments)
     };
-    for _ in 0..max {
-        removers.push(tpe::TpeOptimizer::new(
-            tpe::histogram_estimator(),
-            tpe::categorical_range(offsets1.len() + 1).unwrap(),
-        ));
-    }
-    let max = if offsets2.len() > offsets1.len() {
-        offsets2.len() - offsets1.len()
-    } else {
-        2
+    let mut alignment = Alignment {
+        offset_millis,
+        inliers: segments.iter().map(|s| s.pairs.len()).sum(),
+        residual_ms: 0.,
+        confidence: 0.,
+        pieces,
     };
-    let mut removers2 = vec![];
-    for _ in 0..max {
-        removers2.push(tpe::TpeOptimizer::new(
-            tpe::histogram_estimator(),
-            tpe::categorical_range(offsets2.len() + 1).unwrap(),
-        ));
-    }
-    let mut best_offset = None;
-    let mut smallest_error = None;
-    let mut rng = StdRng::from_seed(Default::default());
-    for _ in tqdm!(0..10000, desc = "Semi Statistic") {
-        let remove_values = removers
-            .iter_mut()
-            .map(|x| x.ask(&mut rng).unwrap())
-            .map(|x| x as usize)
-            .collect::<Vec<_>>();
-        let remove_values2 = removers2
-            .iter_mut()
-            .map(|x| x.ask(&mut rng).unwrap())
-            .map(|x| x as usize)
-            .collect::<Vec<_>>();
-        let tmp_offsets1 = offsets1
+    let residuals = segments
+        .iter()
+        .flat_map(|s| &s.pairs)
+        .map(|p| (p.game + alignment.offset_at(p.game as u64) - p.video).abs() as f64)
+        .collect::<Vec<_>>();
+    alignment.residual_ms = residuals.iter().sum::<f64>() / residuals.len() as f64;
+
+    let pairable = tracks
+        .iter()
+        .map(|t| t.detected.len().min(t.in_match.len()))
+        .sum::<usize>();
+    let inlier_share = alignment.inliers as f64 / pairable as f64;
+    let support = (alignment.inliers as f64 / MIN_INLIERS as f64).min(1.);
+    let precision = 1. - alignment.residual_ms / INLIER_TOLERANCE_MILLIS as f64;
+    alignment.confidence = (inlier_share.min(1.) * support * uniqueness * precision).clamp(0., 1.);
+    debug!("Alignment: {:?}", alignment);
+    Some(alignment)
+}
+
+/// A track in milliseconds.
+#[derive(Debug, Clone)]
+struct Anchors {
+    detected: Vec<i64>,
+    in_match: Vec<i64>,
+}
+
+fn to_millis(times: &[Duration]) -> Vec<i64> {
+    times.iter().map(|t| t.as_millis() as i64).collect()
+}
+
+/// The differences of all pairs of detected and match anchors of a track in `offsets`,
+/// sorted.
+fn votes(tracks: &[Anchors], offsets: &Range<i64>) -> Vec<i64> {
+    let mut votes = tracks
+        .iter()
+        .flat_map(|t| {
+            t.detected
+                .iter()
+                .flat_map(|d| t.in_match.iter().map(move |m| d - m))
+        })
+        .filter(|offset| offsets.contains(offset))
+        .collect::<Vec<_>>();
+    votes.sort_unstable();
+    votes
+}
+
+/// Returns the center and size of the window of width `2 * INLIER_TOLERANCE_MILLIS` with the
+/// most `votes`, skipping windows centered within the given distance of one of the `exclude`d
+/// offsets. Ties go to the smallest offset.
+fn densest_window(votes: &[i64], exclude: &[(i64, i64)]) -> Option<(i64, usize)> {
+    let mut best: Option<(i64, usize)> = None;
+    let mut end = 0;
+    for (start, first) in votes.iter().enumerate() {
+        end = end.max(start);
+        while end < votes.len() && votes[end] - first <= 2 * INLIER_TOLERANCE_MILLIS {
+            end += 1;
+        }
+        let center = median(&votes[start..end]);
+        if exclude
+            .iter()
+            .any(|(offset, distance)| (center - offset).abs() < *distance)
+        {
+            continue;
+        }
+        let count = end - start;
+        if best.is_none_or(|(_, best_count)| count > best_count) {
+            best = Some((center, count));
+        }
+    }
+    best
+}
+
+/// A match anchor and the detected anchor of the same track it is paired with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pair {
+    track: usize,
+    game: i64,
+    video: i64,
+}
+
+impl Pair {
+    fn difference(&self) -> i64 {
+        self.video - self.game
+    }
+}
+
+/// Match anchors that share an offset (or a line, with `drift`) to their detected anchors.
+#[derive(Debug, Clone)]
+struct Segment {
+    offset: i64,
+    drift: f64,
+    /// Sorted by game time.
+    pairs: Vec<Pair>,
+}
+
+impl Segment {
+    /// Collects the inliers of `offset` and moves it to their median difference until it
+    /// settles.
+    fn refine(mut offset: i64, tracks: &[Anchors]) -> Option<Segment> {
+        let mut pairs = vec![];
+        for _ in 0..REFINEMENT_ROUNDS {
+            pairs = inliers(offset, tracks);
+            if pairs.is_empty() {
+                return None;
+            }
+            let refined = median(&pairs.iter().map(Pair::difference).collect::<Vec<_>>());
+            if refined == offset {
+                break;
+            }
+            offset = refined;
+        }
+        Some(Segment {
+            offset,
+            drift: 0.,
+            pairs,
+        })
+    }
+
+    fn range(&self) -> (i64, i64) {
+        (self.pairs[0].game, self.pairs[self.pairs.len() - 1].game)
+    }
+}
+
+/// The match anchors whose closest detected anchor of the same track is within the inlier
+/// tolerance of `offset`, sorted by game time.
+fn inliers(offset: i64, tracks: &[Anchors]) -> Vec<Pair> {
+    let mut pairs = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(track, t)| {
+            t.in_match.iter().filter_map(move |m| {
+                closest(&t.detected, m + offset).map(|video| Pair {
+                    track,
+                    game: *m,
+                    video,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    pairs.sort_by_key(|p| p.game);
+    pairs
+}
+
+/// Votes again on the anchors not explained by the segments found so far. A new segment needs
+/// [`MIN_INLIERS`] anchors and must not overlap the game time of another segment by more than
+/// [`MAX_STRAYS`] pairs, which are moved to it if they fit, otherwise the new segment is most
+/// likely a coincidence. The offsets of new segments may be up to [`MAX_GAP_MILLIS`]
+/// from the first one, even outside `offsets`. Returns the segments sorted by game time.
+fn find_segments(first: Segment, tracks: &[Anchors], offsets: &Range<i64>) -> Vec<Segment> {
+    let offsets = offsets.start.min(first.offset - MAX_GAP_MILLIS)
+        ..offsets.end.max(first.offset + MAX_GAP_MILLIS + 1);
+    let mut segments = vec![first];
+    while segments.len() < MAX_PIECES {
+        let remaining = tracks
             .iter()
             .enumerate()
-            .filter(|(i, _)| !remove_values.contains(i))
-            .map(|(_, x)| *x)
+            .map(|(track, t)| Anchors {
+                detected: t.detected.clone(),
+                in_match: t
+                    .in_match
+                    .iter()
+                    .filter(|m| {
+                        !segments
+                            .iter()
+                            .any(|s| s.pairs.iter().any(|p| p.track == track && p.game == **m))
+                    })
+                    .copied()
+                    .collect(),
+            })
             .collect::<Vec<_>>();
-        let tmp_offsets2 = offsets2
+        let Some((offset, _)) = densest_window(&votes(&remaining, &offsets), &[]) else {
+            break;
+        };
+        let Some(mut segment) = Segment::refine(offset, &remaining) else {
+            break;
+        };
+        let kept = segments
             .iter()
-            .enumerate()
-            .filter(|(i, _)| !remove_values2.contains(i))
-            .map(|(_, x)| *x)
+            .map(|s| without_strays(s, &segment))
             .collect::<Vec<_>>();
-        let offset = match find_best_offset_statistically(&tmp_offsets1, &tmp_offsets2, min_offset)
+        let strays = segments
+            .iter()
+            .zip(&kept)
+            .map(|(s, kept)| s.pairs.len() - kept.len())
+            .sum::<usize>();
+        if segment.pairs.len() < MIN_INLIERS
+            || strays > MAX_STRAYS
+            || kept.iter().any(Vec::is_empty)
         {
-            Some(o) => o,
-            None => continue,
-        };
-        let (offset, error) = offset;
-        for (remover, x) in removers.iter_mut().zip(remove_values.iter()) {
-            remover.tell(*x as f64, error as f64).unwrap();
+            break;
         }
-        for (remover, x) in removers2.iter_mut().zip(remove_values2.iter()) {
-            remover.tell(*x as f64, error as f64).unwrap();
+        for (s, kept) in segments.iter_mut().zip(kept) {
+            let strays = s
+                .pairs
+                .iter()
+                .filter(|p| !kept.contains(p))
+                .copied()
+                .collect::<Vec<_>>();
+            s.pairs = kept;
+            // the anchor of a stray may still have its own detected anchor at the new offset
+            for stray in strays {
+                let track = &tracks[stray.track];
+                let Some(video) = closest(&track.detected, stray.game + segment.offset) else {
+                    continue;
+                };
+                let pair = Pair { video, ..stray };
+                let (start, end) = s.range();
+                let taken = segment
+                    .pairs
+                    .iter()
+                    .any(|p| p.track == pair.track && p.video == video);
+                if !taken && !(start..=end).contains(&pair.game) {
+                    segment.pairs.push(pair);
+                }
+            }
         }
-        if smallest_error.is_none() || error < smallest_error.unwrap() {
-            smallest_error = Some(error);
-            best_offset = Some(offset)
+        segment.pairs.sort_by_key(|p| p.game);
+        segments.push(segment);
+    }
+    segments.sort_by_key(|s| s.range().0);
+    segments
+}
+
+/// The pairs of `segment` that are neither in the game time of `new` nor use one of its
+/// detected anchors, keeping the side of `new` with more of them.
+fn without_strays(segment: &Segment, new: &Segment) -> Vec<Pair> {
+    let (start, end) = new.range();
+    let before = segment.pairs.iter().filter(|p| p.game < start).count();
+    let after = segment.pairs.iter().filter(|p| p.game > end).count();
+    segment
+        .pairs
+        .iter()
+        .filter(|p| match before >= after {
+            true => p.game < start,
+            false => p.game > end,
+        })
+        .filter(|p| {
+            !new.pairs
+                .iter()
+                .any(|n| n.track == p.track && n.video == p.video)
+        })
+        .copied()
+        .collect()
+}
+
+/// The detected anchor closest to `video`, if it is within the inlier tolerance.
+fn closest(detected: &[i64], video: i64) -> Option<i64> {
+    let closest = *detected.iter().min_by_key(|d| (*d - video).abs())?;
+    ((closest - video).abs() <= INLIER_TOLERANCE_MILLIS).then_some(closest)
+}
+
+/// Merges neighbouring segments whose anchors fit a common line within the inlier tolerance,
+/// which is how drift shows up after voting for constant offsets.
+fn merge_drifting(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = vec![];
+    for segment in segments {
+        if let Some(last) = merged.last_mut() {
+            let pairs = last
+                .pairs
+                .iter()
+                .chain(&segment.pairs)
+                .copied()
+                .collect::<Vec<_>>();
+            let (offset, drift) = fit_line(&pairs);
+            let fits = pairs.iter().all(|p| {
+                (offset as f64 + drift * p.game as f64 - p.difference() as f64).abs()
+                    <= INLIER_TOLERANCE_MILLIS as f64
+            });
+            if fits {
+                *last = Segment {
+                    offset,
+                    drift,
+                    pairs,
+                };
+                continue;
+            }
         }
+        merged.push(segment);
     }
-    debug!("Best offset: {:?}", best_offset);
-    debug!("Smallest error: {:?}", smallest_error);
-    best_offset
+    merged
 }
 
-fn find_best_offset_statistically(
-    offsets1: &[u32],
-    offsets2: &[u32],
-    min_offset: u64,
-) -> Option<(u64, u64)> {
-    offsets1
+/// Least squares fit of the differences over the game times, returns the difference at game
+/// time zero and the slope.
+fn fit_line(pairs: &[Pair]) -> (i64, f64) {
+    let n = pairs.len() as f64;
+    let mean_m = pairs.iter().map(|p| p.game as f64).sum::<f64>() / n;
+    let mean_diff = pairs.iter().map(|p| p.difference() as f64).sum::<f64>() / n;
+    let covariance = pairs
         .iter()
-        .cartesian_product(offsets2.iter())
-        .map(|(o1, o2)| *o1 as i32 - *o2 as i32)
-        .map(|o| o as u64)
-        .filter(|offset| *offset < 250000)
-        .filter(|offset| *offset > min_offset)
-        .filter_map(|offset| get_error(offset, offsets1, offsets2).map(|error| (offset, error)))
-        .min_by_key(|(_, error)| *error)
-        .and_then(|(offset, error)| (error < 200).then_some((offset, error)))
-}
-
-fn get_error(offset: u64, offsets1: &[u32], offsets2: &[u32]) -> Option<u64> {
-    let errors = offsets2
+        .map(|p| (p.game as f64 - mean_m) * (p.difference() as f64 - mean_diff))
+        .sum::<f64>();
+    let variance = pairs
         .iter()
-        .map(|o| *o as u64)
-        .map(|x| x + offset)
-        .map(|x| x as i64 - get_closest_offset(x, offsets1) as i64)
-        .filter(|x| *x > 0)
-        .map(|x| x.unsigned_abs())
-        .collect::<Vec<_>>();
-    (!errors.is_empty()).then(|| errors.iter().sum::<u64>() / errors.len() as u64)
+        .map(|p| (p.game as f64 - mean_m).powi(2))
+        .sum::<f64>();
+    let drift = if variance > 0. {
+        covariance / variance
+    } else {
+        0.
+    };
+    ((mean_diff - drift * mean_m).round() as i64, drift)
 }
 
-fn get_closest_offset(offset: u64, offsets: &[u32]) -> u32 {
-    *offsets
+/// Turns segments sorted by game time into pieces that switch halfway between the last anchor
+/// of a segment and the first anchor of the next one.
+fn to_pieces(segments: &[Segment]) -> Vec<WarpPiece> {
+    segments
         .iter()
-        .min_by_key(|x| (offset as i64 - **x as i64).abs())
-        .unwrap()
+        .enumerate()
+        .map(|(i, segment)| {
+            let from = match i {
+                0 => 0,
+                _ => (segments[i - 1].range().1 + segment.range().0) / 2,
+            };
+            WarpPiece {
+                from_game_millis: from as u64,
+                offset_millis: segment.offset + (segment.drift * from as f64).round() as i64,
+                drift: segment.drift,
+            }
+        })
+        .collect()
+}
+
+fn median(values: &[i64]) -> i64 {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::Scenario;
+
+    const CASES: usize = 200;
+    const SEED: u64 = 1;
+
+    /// Checks every generated case of `scenario` on its own, so a regression can't hide in an
+    /// aggregate accuracy.
+    fn assert_aligns_each(scenario: Scenario) {
+        for case in scenario.generate(CASES, SEED) {
+            let result = case.run();
+            assert!(
+                result
+                    .error_ms
+                    .is_some_and(|e| e <= INLIER_TOLERANCE_MILLIS as f64),
+                "{result:?}"
+            );
+        }
+    }
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    fn align_kills(in_match: &[u64], detected: &[u64]) -> Option<Alignment> {
+        let (in_match, detected) = (millis(in_match), millis(detected));
+        align(
+            &[Track {
+                detected: &detected,
+                in_match: &in_match,
+            }],
+            0..MAX_OFFSET_MILLIS,
+        )
+    }
+
+    #[test]
+    fn finds_constant_offset() {
+        let alignment = align_kills(
+            &[10_000, 25_000, 40_000, 70_000, 90_000],
+            &[5_000, 72_100, 87_000, 102_000, 132_000, 152_000],
+        )
+        .unwrap();
+        assert_eq!(alignment.offset_millis, 62_000);
+        assert_eq!(alignment.inliers, 5);
+        assert!(alignment.pieces.is_empty());
+    }
+
+    #[test]
+    fn evenly_spaced_kills_are_ambiguous() {
+        let in_match = [30_000, 60_000, 90_000, 120_000, 150_000];
+        let detected = in_match.map(|m| m + 62_000);
+        let alignment = align_kills(&in_match, &detected).unwrap();
+        assert_eq!(alignment.offset_millis, 62_000);
+        // shifting by one kill still explains four of the five
+        assert!(alignment.confidence < 0.4, "{alignment:?}");
+    }
+
+    #[test]
+    fn few_anchors_lower_the_confidence() {
+        let alignment = align_kills(&[10_000, 37_000], &[72_000, 99_000]).unwrap();
+        assert_eq!(alignment.offset_millis, 62_000);
+        assert!(alignment.confidence <= 0.5, "{alignment:?}");
+    }
+
+    #[test]
+    fn finds_nothing_outside_the_offsets() {
+        // the video would start 20 s after the game, but only positive offsets are searched
+        let in_match = [30_000, 33_000, 37_000, 42_000, 45_000];
+        let detected = in_match.map(|m| m - 20_000);
+        assert_eq!(align_kills(&in_match, &detected), None);
+    }
+
+    /// Detected kills of `in_match` moved by `before` until `gap_at` and by `after` from there.
+    fn with_gap(in_match: &[u64], gap_at: u64, before: i64, after: i64) -> Vec<u64> {
+        in_match
+            .iter()
+            .map(|m| match *m < gap_at {
+                true => m.saturating_add_signed(before),
+                false => m.saturating_add_signed(after),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_gap_that_moves_later_anchors_earlier() {
+        let in_match = [
+            10_000, 31_000, 57_500, 83_000, 400_000, 426_500, 441_000, 478_000,
+        ];
+        // the VOD misses a minute between the fourth and fifth kill
+        let detected = with_gap(&in_match, 200_000, 20_000, -40_000);
+        let alignment = align_kills(&in_match, &detected).unwrap();
+        assert_eq!(alignment.offset_at(31_000), 20_000);
+        assert_eq!(alignment.offset_at(426_500), -40_000);
+        assert_eq!(alignment.inliers, 8);
+    }
+
+    #[test]
+    fn finds_pause_that_moves_later_anchors_later() {
+        let in_match = [
+            12_000, 40_500, 61_000, 97_000, 130_000, 305_000, 322_500, 369_000, 401_000,
+        ];
+        // the stream was paused for 90 s between the fifth and sixth kill
+        let detected = with_gap(&in_match, 200_000, 45_000, 135_000);
+        let alignment = align_kills(&in_match, &detected).unwrap();
+        assert_eq!(alignment.pieces.len(), 2, "{alignment:?}");
+        assert!(in_match[..5]
+            .iter()
+            .all(|m| alignment.offset_at(*m) == 45_000));
+        assert!(in_match[5..]
+            .iter()
+            .all(|m| alignment.offset_at(*m) == 135_000));
+        assert_eq!(alignment.inliers, 9);
+    }
+
+    #[test]
+    fn merges_drift_into_one_piece() {
+        let in_match = [
+            20_000, 95_000, 160_000, 240_000, 410_000, 530_000, 610_000, 780_000, 910_000,
+            1_020_000, 1_150_000, 1_320_000, 1_480_000, 1_555_000, 1_700_000, 1_930_000, 2_010_000,
+            2_080_000,
+        ];
+        // the video runs 1 ms per game second slow, 2 s over the match
+        let detected = in_match.map(|m| m + 30_000 + m / 1000);
+        let alignment = align_kills(&in_match, &detected).unwrap();
+        assert_eq!(alignment.pieces.len(), 1, "{alignment:?}");
+        assert!((alignment.pieces[0].drift - 0.001).abs() < 0.0001);
+        assert!(in_match
+            .iter()
+            .all(|m| (alignment.offset_at(*m) - (30_000 + *m as i64 / 1000)).abs() <= 50));
+    }
+
+    #[test]
+    fn keeps_too_few_anchors_out_of_pieces() {
+        let in_match = [
+            10_000, 31_000, 57_500, 83_000, 121_000, 160_000, 400_000, 426_500, 478_000,
+        ];
+        let detected = with_gap(&in_match, 200_000, 20_000, -40_000);
+        let alignment = align_kills(&in_match, &detected).unwrap();
+        // three kills after the gap could just as well be coincidences
+        assert!(alignment.pieces.is_empty(), "{alignment:?}");
+        assert_eq!(alignment.offset_millis, 20_000);
+        assert_eq!(alignment.inliers, 6);
+    }
+
+    #[test]
+    fn aligns_generated_matches() {
+        assert_aligns_each(Scenario::default());
+    }
+
+    #[test]
+    fn aligns_noisy_detections() {
+        assert_aligns_each(Scenario {
+            noise_millis: 400,
+            miss_rate: 0.2,
+            false_positives: 10,
+            ..Scenario::default()
+        });
+    }
+
+    #[test]
+    fn aligns_drifting_videos() {
+        assert_aligns_each(Scenario {
+            drift: 0.0002,
+            ..Scenario::default()
+        });
+    }
+
+    #[test]
+    fn aligns_each_side_of_generated_gaps() {
+        let scenario = Scenario {
+            gap_millis: 60_000,
+            miss_rate: 0.,
+            false_positives: 0,
+            ..Scenario::default()
+        };
+        let min_confidence = crate::Config::default().min_confidence;
+        for case in scenario.generate(CASES, SEED) {
+            let alignment = align_kills(&case.in_match_millis, &case.detected_millis).unwrap();
+            let gap_at = case.expected.pieces[1].from_game_millis;
+            let (before, after): (Vec<u64>, Vec<u64>) =
+                case.in_match_millis.iter().partition(|m| **m < gap_at);
+            // a side with fewer than MIN_INLIERS kills can't be told apart from coincidences
+            let sides = [before, after]
+                .into_iter()
+                .zip(&case.expected.pieces)
+                .filter(|(side, _)| side.len() >= MIN_INLIERS)
+                .collect::<Vec<_>>();
+            let wrong = sides
+                .iter()
+                .flat_map(|(side, piece)| side.iter().map(move |m| (m, piece.offset_millis)))
+                .map(|(m, offset)| (m, alignment.offset_at(*m) - offset))
+                .find(|(_, error)| error.abs() > INLIER_TOLERANCE_MILLIS);
+            // without a side in the searched offsets the case only has to be refused
+            let searched = sides
+                .iter()
+                .any(|(_, piece)| (0..MAX_OFFSET_MILLIS).contains(&piece.offset_millis));
+            if let Some((m, error)) = wrong {
+                assert!(
+                    !searched && alignment.confidence < min_confidence,
+                    "{} off by {error} ms at {m}: {alignment:?}",
+                    case.name
+                );
+            }
+            if sides.len() == 2 {
+                assert!(
+                    alignment.confidence >= min_confidence,
+                    "{}: {alignment:?}",
+                    case.name
+                );
+            }
+        }
+    }
 }
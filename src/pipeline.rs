@@ -0,0 +1,1585 @@
+use crate::anchors::{self, AnchorKind, Anchors};
+use crate::calibration;
+use crate::config::{Config, Debounce};
+use crate::detections::{self, DetectionKey};
+use crate::detector;
+use crate::error::{Error, Result};
+use crate::events::{self, Event, MatchEvent};
+use crate::hud::{self, HudProfile};
+use crate::manifest::{self, ClipStatus, Manifest, ManifestClip};
+use crate::offset::{Alignment, AlignmentMethod, AlignmentRecord};
+use crate::stages::{self, ArtifactPaths, MatchList, Timeline};
+use crate::state::{FsStateStore, StateStore};
+use crate::video::{EarlyStop, MatchWindow, Metadata};
+use crate::{local, offset, twitch, valorant, video};
+use filetime_creation::{set_file_times, FileTime};
+use futures::StreamExt;
+use itertools::Itertools;
+use kdam::{tqdm, BarExt};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use time::format_description::well_known::Rfc3339;
+use time::{format_description, OffsetDateTime};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+use valorant_api_official::enums::queue::Queue;
+use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
+
+lazy_static! {
+    static ref CLIP_DATE_TIME_PREFIX: Vec<format_description::FormatItem<'static>> =
+        format_description::parse("[day]-[month]-[year]_[hour]-[minute]-[second]").unwrap();
+    static ref CLIP_DATE_PREFIX: Vec<format_description::FormatItem<'static>> =
+        format_description::parse("[day]-[month]-[year]").unwrap();
+    static ref CLIP_PADDING: (Duration, Duration) =
+        (Duration::from_secs(10), Duration::from_secs(10));
+}
+
+/// How much later an anchor is detected than it happens, used for detection models that are
+/// not calibrated.
+pub const DETECTOR_LATENCY_MILLIS: i64 = 350;
+/// How far from the aligned time the refinement pass looks for a kill banner.
+const REFINEMENT_WINDOW: Duration = Duration::from_millis(1500);
+
+/// Where the footage of a stream comes from.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum VodSource {
+    Twitch(usize),
+    /// A local recording. Without `start` the recording start is read from the file.
+    Local {
+        path: PathBuf,
+        start: Option<OffsetDateTime>,
+    },
+}
+
+impl VodSource {
+    /// Identifier of the source used in state markers and output directories. Local
+    /// recordings are named by their file stem and a hash of their full path, so recordings
+    /// with the same name in different directories don't share state.
+    pub fn id(&self) -> String {
+        match self {
+            VodSource::Twitch(vod_id) => vod_id.to_string(),
+            VodSource::Local { path, .. } => {
+                let stem = path
+                    .file_stem()
+                    .map_or("local".to_string(), |s| s.to_string_lossy().to_string());
+                let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+                let hash = manifest::fnv1a(path.to_string_lossy().as_bytes());
+                format!("{stem}-{:08x}", hash as u32)
+            }
+        }
+    }
+
+    pub async fn interval(&self) -> Result<(OffsetDateTime, OffsetDateTime)> {
+        match self {
+            VodSource::Twitch(vod_id) => twitch::get_vod_start_end(*vod_id).await,
+            VodSource::Local { path, start } => local::get_video_start_end(path, *start),
+        }
+    }
+
+    /// Stores the part between `start` and `end` of the source at `out_path`.
+    pub fn save_video(&self, out_path: &Path, start: Duration, end: Duration) -> Result<()> {
+        match self {
+            VodSource::Twitch(vod_id) => {
+                let status = valorant::save_match_video(out_path, *vod_id, start, end)
+                    .map_err(|e| Error::Download(e.to_string()))?;
+                if !status.success() {
+                    return Err(Error::Download(format!("Download exited with {status}")));
+                }
+            }
+            VodSource::Local { path, .. } => local::save_video_part(path, out_path, start, end)
+                .map_err(|e| Error::Download(e.to_string()))?,
+        }
+        if !out_path.exists() {
+            return Err(Error::Download(format!(
+                "{} was not created",
+                out_path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Which matches and events of a VOD end up as clips.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Filters {
+    pub category: Option<Vec<String>>,
+    pub exclude_category: Option<Vec<String>>,
+    pub only_customs: bool,
+    pub matches_after: u64,
+    pub matches_before: u64,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            category: None,
+            exclude_category: None,
+            only_customs: false,
+            matches_after: 0,
+            matches_before: u64::MAX,
+        }
+    }
+}
+
+/// A clip that was cut from a VOD.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipResult {
+    pub vod_id: String,
+    pub match_id: Uuid,
+    pub category: String,
+    pub path: PathBuf,
+    pub event: Event,
+}
+
+/// The outcome of processing one match of a VOD.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchResult {
+    pub vod_id: String,
+    pub match_id: Uuid,
+    pub clips: Result<Vec<ClipResult>>,
+}
+
+/// The outcome of processing one VOD. `matches` is an error if the VOD or its matches could
+/// not be discovered.
+#[derive(Debug, Clone, Serialize)]
+pub struct VodResult {
+    pub vod_id: String,
+    pub matches: Result<Vec<MatchResult>>,
+}
+
+/// What a run would do, as produced by [`Pipeline::plan`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub puuids: HashSet<String>,
+    pub vods: Vec<VodPlan>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VodPlan {
+    pub vod_id: String,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub matches: Vec<MatchPlan>,
+    pub error: Option<Error>,
+}
+
+/// A discovered match. Matches with a `skip_reason` would not be processed.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchPlan {
+    pub match_id: Uuid,
+    pub game_start_millis: u64,
+    pub skip_reason: Option<String>,
+    pub clips: Vec<PlannedClip>,
+    pub error: Option<Error>,
+}
+
+/// A clip that would be cut. The times are game times, the VOD timestamp in `path` assumes
+/// no offset between game and video.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedClip {
+    pub category: String,
+    pub start_millis: u64,
+    pub end_millis: u64,
+    pub path: PathBuf,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PipelineBuilder {
+    sources: Vec<VodSource>,
+    riot_ids: Vec<String>,
+    match_files: Vec<PathBuf>,
+    filters: Filters,
+    force: bool,
+    remove_matches: bool,
+    config: Config,
+    state: Option<Arc<dyn StateStore>>,
+}
+
+impl PipelineBuilder {
+    pub fn vod(mut self, vod_id: usize) -> Self {
+        self.sources.push(VodSource::Twitch(vod_id));
+        self
+    }
+
+    pub fn vods(mut self, vod_ids: impl IntoIterator<Item = usize>) -> Self {
+        self.sources
+            .extend(vod_ids.into_iter().map(VodSource::Twitch));
+        self
+    }
+
+    pub fn sources(mut self, sources: impl IntoIterator<Item = VodSource>) -> Self {
+        self.sources.extend(sources);
+        self
+    }
+
+    /// Adds a local recording, see [`VodSource::Local`].
+    pub fn local_file(mut self, path: impl Into<PathBuf>, start: Option<OffsetDateTime>) -> Self {
+        self.sources.push(VodSource::Local {
+            path: path.into(),
+            start,
+        });
+        self
+    }
+
+    /// Adds a player by Riot ID (`name#tag`) or PUUID.
+    pub fn riot_id(mut self, riot_id: impl Into<String>) -> Self {
+        self.riot_ids.push(riot_id.into());
+        self
+    }
+
+    pub fn riot_ids<S: Into<String>>(mut self, riot_ids: impl IntoIterator<Item = S>) -> Self {
+        self.riot_ids.extend(riot_ids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Discovers matches from saved `MatchDetailsV1` JSON files (or directories of them)
+    /// instead of the Riot API.
+    pub fn match_files<P: Into<PathBuf>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.match_files.extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn filters(mut self, filters: Filters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Reprocesses matches that are already marked as processed or failed.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Deletes the downloaded match video once its clips are cut.
+    pub fn remove_matches(mut self, remove_matches: bool) -> Self {
+        self.remove_matches = remove_matches;
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Replaces the default [`FsStateStore`] rooted at [`Config::state_dir`].
+    pub fn state_store(mut self, state: impl StateStore + 'static) -> Self {
+        self.state = Some(Arc::new(state));
+        self
+    }
+
+    pub fn build(self) -> Pipeline {
+        let state = self
+            .state
+            .unwrap_or_else(|| Arc::new(FsStateStore::new(&self.config.state_dir)));
+        let limit = |n: usize| Arc::new(Semaphore::new(n.max(1)));
+        Pipeline {
+            downloads: limit(self.config.concurrency.downloads),
+            inference: limit(self.config.concurrency.inference),
+            cuts: limit(self.config.concurrency.cuts),
+            bars: Arc::default(),
+            latencies: Arc::default(),
+            probed_profiles: Arc::default(),
+            config: self.config,
+            state,
+            sources: self.sources,
+            riot_ids: self.riot_ids,
+            match_files: self.match_files,
+            filters: self.filters,
+            force: self.force,
+            remove_matches: self.remove_matches,
+        }
+    }
+}
+
+/// Finds the matches of a set of players in their VODs and cuts clips of the interesting events.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    config: Config,
+    state: Arc<dyn StateStore>,
+    downloads: Arc<Semaphore>,
+    inference: Arc<Semaphore>,
+    cuts: Arc<Semaphore>,
+    bars: Arc<BarPositions>,
+    /// Detector latencies by model hash and HUD profile, looked up on first use.
+    latencies: Arc<Mutex<HashMap<(String, String), i64>>>,
+    /// HUD profiles probed for match videos.
+    probed_profiles: Arc<Mutex<HashMap<PathBuf, (String, HudProfile)>>>,
+    sources: Vec<VodSource>,
+    riot_ids: Vec<String>,
+    match_files: Vec<PathBuf>,
+    filters: Filters,
+    force: bool,
+    remove_matches: bool,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Processes all sources. Failures of single VODs or matches are reported in the results
+    /// and do not stop the run.
+    ///
+    /// VODs and their matches are processed concurrently, bounded by [`Config::concurrency`].
+    pub async fn run(&self) -> Result<Vec<VodResult>> {
+        let puuids = self.resolve_puuids().await?;
+        Ok(futures::stream::iter(&self.sources)
+            .map(|source| async {
+                let matches = self.process_vod(source, &puuids).await;
+                if let Err(e) = &matches {
+                    error!("Failed to process {}: {}", source.id(), e);
+                }
+                VodResult {
+                    vod_id: source.id(),
+                    matches,
+                }
+            })
+            .buffered(self.config.concurrency.vods.max(1))
+            .collect()
+            .await)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn state(&self) -> &dyn StateStore {
+        self.state.as_ref()
+    }
+
+    /// The content hash of the detection model at `model_path`, see
+    /// [`calibration::model_hash`].
+    pub fn model_hash(&self, model_path: &Path) -> Option<String> {
+        calibration::model_hash(model_path)
+            .map_err(|e| warn!("Failed to read model {}: {}", model_path.display(), e))
+            .ok()
+    }
+
+    /// The calibrated lag of the detection model with `model_hash` in the area of
+    /// `hud_profile`, or [`DETECTOR_LATENCY_MILLIS`] if the model is unknown or not calibrated
+    /// for the profile.
+    pub fn detector_latency(&self, model_hash: Option<&str>, hud_profile: &str) -> i64 {
+        let Some(model_hash) = model_hash else {
+            return DETECTOR_LATENCY_MILLIS;
+        };
+        *self
+            .latencies
+            .lock()
+            .unwrap()
+            .entry((model_hash.to_string(), hud_profile.to_string()))
+            .or_insert_with(
+                || match self.state.load_calibration(model_hash, hud_profile) {
+                    Some(calibration) => calibration.latency_millis,
+                    None => {
+                        debug!(
+                            "Detection model {} is not calibrated for HUD profile {}",
+                            model_hash, hud_profile
+                        );
+                        DETECTOR_LATENCY_MILLIS
+                    }
+                },
+            )
+    }
+
+    /// The lag of the detector of `kind` that found the anchors in `timeline`.
+    pub fn timeline_latency(&self, timeline: &Timeline, kind: AnchorKind) -> i64 {
+        self.detector_latency(
+            timeline.model_hashes.get(&kind).map(String::as_str),
+            &timeline.hud_profile,
+        )
+    }
+
+    /// The HUD profile of the match video at `match_video_path` and its name. With
+    /// [`hud::AUTO`] every profile is tried on samples of the video and the one whose kill
+    /// detector sees banners most clearly is used, see [`video::probe_signal`].
+    pub async fn hud_profile(&self, match_video_path: &Path) -> Result<(String, HudProfile)> {
+        let profiles = self.config.hud_profiles();
+        if self.config.hud_profile != hud::AUTO {
+            return profiles
+                .into_iter()
+                .find(|(name, _)| *name == self.config.hud_profile)
+                .ok_or_else(|| {
+                    Error::Config(format!("Unknown HUD profile: {}", self.config.hud_profile))
+                });
+        }
+        if let Some(probed) = self.probed_profiles.lock().unwrap().get(match_video_path) {
+            return Ok(probed.clone());
+        }
+
+        let length = local::get_duration(match_video_path)
+            .map_err(|e| Error::Detection(format!("Failed to probe match video: {e}")))?;
+        let mut best: Option<(i64, String, HudProfile)> = None;
+        for (name, profile) in profiles {
+            let (Some(model_path), Some(crop)) = (
+                profile.model(&self.config, AnchorKind::Kill),
+                profile.filter(AnchorKind::Kill),
+            ) else {
+                continue;
+            };
+            let (video_path, model_path, backend, threshold) = (
+                match_video_path.to_path_buf(),
+                model_path.to_path_buf(),
+                self.config.detector,
+                self.config.primary_debounce().threshold,
+            );
+            let signal = run_limited(&self.inference, move || {
+                let detector = detector::load(backend, &model_path)?;
+                video::probe_signal(&video_path, detector.as_ref(), &crop, length, threshold)
+            })
+            .await;
+            match signal {
+                Ok(signal) => {
+                    debug!("HUD profile {} has signal {}", name, signal);
+                    if best.as_ref().is_none_or(|(best, _, _)| signal > *best) {
+                        best = Some((signal, name, profile));
+                    }
+                }
+                Err(e) => warn!("Failed to probe HUD profile {}: {}", name, e),
+            }
+        }
+        let (signal, name, profile) =
+            best.ok_or_else(|| Error::Detection("No HUD profile could be probed".to_string()))?;
+        info!("Using HUD profile {} with signal {}", name, signal);
+        self.probed_profiles.lock().unwrap().insert(
+            match_video_path.to_path_buf(),
+            (name.clone(), profile.clone()),
+        );
+        Ok((name, profile))
+    }
+
+    /// Whether matches are discovered from saved match files only.
+    pub fn is_offline(&self) -> bool {
+        !self.match_files.is_empty()
+    }
+
+    pub async fn resolve_puuids(&self) -> Result<HashSet<String>> {
+        if self.is_offline() {
+            let matches = self.load_match_files()?;
+            return Ok(self
+                .riot_ids
+                .iter()
+                .filter_map(|id| {
+                    let puuid = valorant::get_puuid_offline(id, &matches);
+                    if puuid.is_none() {
+                        warn!("Player {} not found in match files", id);
+                    }
+                    puuid
+                })
+                .collect());
+        }
+        Ok(
+            futures::future::join_all(self.riot_ids.iter().map(|id| valorant::get_puuid(id)))
+                .await
+                .into_iter()
+                .filter_map(|x| x.map_err(|e| warn!("{}", e)).ok())
+                .collect(),
+        )
+    }
+
+    fn load_match_files(&self) -> Result<Vec<MatchDetailsV1>> {
+        valorant::load_match_details(&self.match_files)
+            .map_err(|e| Error::Discovery(format!("Failed to load match files: {e}")))
+    }
+
+    pub async fn process_vod(
+        &self,
+        source: &VodSource,
+        puuids: &HashSet<String>,
+    ) -> Result<Vec<MatchResult>> {
+        let vod_id = source.id();
+        let vod_interval = source.interval().await?;
+        let matches = self
+            .discover_matches(puuids, &vod_id, vod_interval, self.force)
+            .await?;
+
+        let matches = matches
+            .into_iter()
+            .filter(|valo_match| match self.skip_reason(valo_match) {
+                Some(reason) => {
+                    debug!(
+                        "Skipping match: {:?} {}",
+                        valo_match.match_info.match_id, reason
+                    );
+                    false
+                }
+                None => true,
+            })
+            .collect_vec();
+        self.save_match_list(MatchList {
+            vod_id,
+            start: vod_interval.0.format(&Rfc3339).unwrap(),
+            end: vod_interval.1.format(&Rfc3339).unwrap(),
+            puuids: puuids.clone(),
+            matches: matches.clone(),
+        });
+
+        Ok(futures::stream::iter(&matches)
+            .map(|valo_match| self.process_and_mark(puuids, source, vod_interval, valo_match))
+            .buffer_unordered(self.config.concurrency.matches.max(1))
+            .collect()
+            .await)
+    }
+
+    async fn process_and_mark(
+        &self,
+        puuids: &HashSet<String>,
+        source: &VodSource,
+        vod_interval: (OffsetDateTime, OffsetDateTime),
+        valo_match: &MatchDetailsV1,
+    ) -> MatchResult {
+        let vod_id = source.id();
+        let match_id = valo_match.match_info.match_id;
+        let clips = self
+            .process_match(puuids, source, vod_interval, valo_match)
+            .await;
+        let written = match &clips {
+            Ok(_) => self.state.mark_processed(&vod_id, match_id),
+            Err(e) => {
+                error!("Failed to process match {}: {}", match_id, e);
+                self.state.mark_failed(&vod_id, match_id, e)
+            }
+        };
+        if let Err(e) = written {
+            warn!("Failed to mark match {}: {}", match_id, e);
+        }
+        MatchResult {
+            vod_id,
+            match_id,
+            clips,
+        }
+    }
+
+    /// Runs match discovery and event building only and reports what a run would do.
+    pub async fn plan(&self) -> Result<Plan> {
+        let puuids = self.resolve_puuids().await?;
+        let mut vods = vec![];
+        for source in &self.sources {
+            vods.push(match self.plan_vod(source, &puuids).await {
+                Ok(plan) => plan,
+                Err(e) => VodPlan {
+                    vod_id: source.id(),
+                    start: None,
+                    end: None,
+                    matches: vec![],
+                    error: Some(e),
+                },
+            });
+        }
+        Ok(Plan { puuids, vods })
+    }
+
+    async fn plan_vod(&self, source: &VodSource, puuids: &HashSet<String>) -> Result<VodPlan> {
+        let vod_id = source.id();
+        let vod_interval = source.interval().await?;
+        let matches = self
+            .discover_matches(puuids, &vod_id, vod_interval, true)
+            .await?;
+
+        let mut match_plans = vec![];
+        for valo_match in matches {
+            let match_id = valo_match.match_info.match_id;
+            let skip_reason = match self.skip_reason(&valo_match) {
+                Some(reason) => Some(reason.to_string()),
+                None if !self.force && self.state.is_marked(&vod_id, match_id) => {
+                    Some("already processed or failed".to_string())
+                }
+                None => None,
+            };
+            let mut clips = vec![];
+            let mut error = None;
+            if skip_reason.is_none() {
+                for event in self.select_events(puuids, &valo_match).await {
+                    let (start, end) = event.game_time_interval();
+                    let (category, path) = match self
+                        .clip_path(&event, puuids, &valo_match, &vod_id, vod_interval.0, start)
+                        .await
+                    {
+                        Ok(clip_path) => clip_path,
+                        Err(e) => {
+                            error = Some(e);
+                            break;
+                        }
+                    };
+                    clips.push(PlannedClip {
+                        category,
+                        start_millis: start.as_millis() as u64,
+                        end_millis: end.as_millis() as u64,
+                        path,
+                        event,
+                    });
+                }
+            }
+            match_plans.push(MatchPlan {
+                match_id,
+                game_start_millis: valo_match.match_info.game_start_millis,
+                skip_reason,
+                clips,
+                error,
+            });
+        }
+        Ok(VodPlan {
+            vod_id,
+            start: vod_interval.0.format(&Rfc3339).ok(),
+            end: vod_interval.1.format(&Rfc3339).ok(),
+            matches: match_plans,
+            error: None,
+        })
+    }
+
+    async fn discover_matches(
+        &self,
+        puuids: &HashSet<String>,
+        vod_id: &str,
+        vod_interval: (OffsetDateTime, OffsetDateTime),
+        force: bool,
+    ) -> Result<Vec<MatchDetailsV1>> {
+        if self.is_offline() {
+            let matches = self.load_match_files()?;
+            Ok(valorant::find_valorant_matches_offline(
+                matches,
+                puuids,
+                vod_interval,
+                vod_id,
+                force,
+                self.state.as_ref(),
+            ))
+        } else {
+            valorant::find_valorant_matches_by_players(
+                puuids,
+                vod_interval,
+                vod_id,
+                force,
+                &self.config.cache_dir,
+                self.state.as_ref(),
+            )
+            .await
+        }
+    }
+
+    fn skip_reason(&self, valo_match: &MatchDetailsV1) -> Option<&'static str> {
+        let filters = &self.filters;
+        if filters.only_customs && valo_match.match_info.provisioning_flow_id != "CustomGame" {
+            return Some("not a custom game");
+        }
+        if filters.only_customs && valo_match.players.len() < 10 {
+            return Some("not enough players");
+        }
+        if valo_match.match_info.game_start_millis < filters.matches_after {
+            return Some("before matches_after");
+        }
+        if valo_match.match_info.game_start_millis > filters.matches_before {
+            return Some("after matches_before");
+        }
+        None
+    }
+
+    /// Builds the events of the tracked players that pass the category filters.
+    async fn select_events(
+        &self,
+        puuids: &HashSet<String>,
+        valo_match: &MatchDetailsV1,
+    ) -> Vec<Event> {
+        let category = &self.filters.category;
+        let exclude_category = &self.filters.exclude_category;
+        debug!(
+            "Filtering for category: {:?} excluding category: {:?}",
+            category, exclude_category
+        );
+        let events = events::build_events(valo_match)
+            .into_iter()
+            .filter(|e| match e {
+                Event::Kill(e) => e.is_from_puuids(puuids) || e.is_against_puuids(puuids),
+                Event::MultiKill(e) => e.is_from_puuids(puuids),
+                Event::Clutch(e) => e.is_from_puuids(puuids),
+                Event::DoubleKill(e) => e.is_from_puuids(puuids),
+                Event::Plant(e) => e.is_from_puuids(puuids),
+                Event::Defuse(e) => e.is_from_puuids(puuids),
+                Event::Ace(e) => e.is_from_puuids(puuids),
+                Event::Retake(e) => e.is_from_puuids(puuids),
+            })
+            .collect_vec();
+
+        let mut filtered_events = vec![];
+        for event in events {
+            if let Some(exclude_category) = exclude_category.as_ref() {
+                if exclude_category.contains(&event.category(puuids).await) {
+                    continue;
+                }
+            }
+            if let Some(category) = category.as_ref() {
+                if category.contains(&event.category(puuids).await) {
+                    filtered_events.push(event);
+                }
+            } else {
+                filtered_events.push(event);
+            }
+        }
+        filtered_events
+    }
+
+    /// Returns the category and output path of the clip of `event`, where `start` is the
+    /// position of the event in the match video.
+    async fn clip_path(
+        &self,
+        event: &Event,
+        puuids: &HashSet<String>,
+        valo_match: &MatchDetailsV1,
+        vod_id: &str,
+        vod_start: OffsetDateTime,
+        start: Duration,
+    ) -> Result<(String, PathBuf)> {
+        let category = event.category(puuids).await;
+        let name_postfix = event
+            .name_postfix(valo_match)
+            .await
+            .replace([' ', '/'], "_");
+        let match_date = match_date(valo_match)?;
+        let map_id = &valo_match.match_info.map_id;
+        let map_name = match valorant::get_map_name(map_id).await {
+            Ok(Some(map_name)) => map_name,
+            // offline the last part of the map path, like `/Game/Maps/Ascent/Ascent`, still
+            // names the map
+            result => {
+                if let Err(e) = result {
+                    warn!("Failed to get map name of {}: {}", map_id, e);
+                }
+                map_id.rsplit('/').next().unwrap_or(map_id).to_string()
+            }
+        };
+
+        let event_date = (match_date + event.game_time_interval().0)
+            .format(&CLIP_DATE_TIME_PREFIX)
+            .unwrap();
+
+        // timestamp of event in vod, the match video starts at the VOD start if the match
+        // started earlier
+        let event_vod_time = (match_date - vod_start).max(time::Duration::ZERO) + start;
+        let seconds = event_vod_time.whole_seconds();
+        let hours = seconds / 3600;
+        let minutes = (seconds % 3600) / 60;
+        let seconds = seconds % 60;
+        let event_vod_time = format!("{hours:02}-{minutes:02}-{seconds:02}");
+
+        let clip_name = format!("{event_vod_time}_{event_date}_{map_name}_{name_postfix}.mp4");
+        let clip_path = self
+            .config
+            .output_dir
+            .join(format!(
+                "{}_{}",
+                vod_id,
+                vod_start.format(&CLIP_DATE_PREFIX).unwrap()
+            ))
+            .join(game_mode(valo_match))
+            .join(&category)
+            .join(clip_name);
+        Ok((category, clip_path))
+    }
+
+    async fn process_match(
+        &self,
+        puuids: &HashSet<String>,
+        source: &VodSource,
+        vod_interval: (OffsetDateTime, OffsetDateTime),
+        valo_match: &MatchDetailsV1,
+    ) -> Result<Vec<ClipResult>> {
+        let events = self.select_events(puuids, valo_match).await;
+
+        info!("Found {} events", events.len());
+
+        if events.is_empty() {
+            return Err(Error::Discovery(
+                "No events of the tracked players".to_string(),
+            ));
+        }
+        let match_anchors = anchors::match_anchors(valo_match, puuids)?;
+
+        let vod_id = source.id();
+        let match_id = valo_match.match_info.match_id;
+        let previous = self.state.load_manifest(&vod_id, match_id);
+        let known_alignment = self.known_alignment(&vod_id, match_id).or_else(|| {
+            previous
+                .as_ref()
+                .filter(|_| !self.force)
+                .map(|previous| previous.alignment.clone())
+        });
+        if let Some(alignment) = known_alignment.clone() {
+            let manifest = self
+                .plan_manifest(
+                    &events,
+                    puuids,
+                    valo_match,
+                    &vod_id,
+                    vod_interval.0,
+                    alignment,
+                )
+                .await?
+                .with_previous(previous.as_ref());
+            if manifest.is_complete() {
+                info!("All clips of match {} already exist", match_id);
+                self.save_manifest(&manifest);
+                return Ok(clip_results(&manifest, &events));
+            }
+        }
+
+        let match_video_path = self
+            .download_match(source, vod_interval, valo_match)
+            .await?;
+
+        let alignment = match known_alignment {
+            Some(alignment) => alignment,
+            None => {
+                let window = video::get_match_interval(vod_interval, valo_match)?;
+                let timeline = self
+                    .detect(&match_video_path, valo_match, window, &match_anchors)
+                    .await?;
+                let timeline_path =
+                    ArtifactPaths::new(&self.config.scratch_dir, &vod_id).timeline(match_id);
+                if let Err(e) = stages::save(&timeline_path, &timeline, Error::Detection) {
+                    warn!("{}", e);
+                }
+                let alignment = align(&timeline, &match_anchors, &self.config.debounce, |kind| {
+                    self.timeline_latency(&timeline, kind)
+                })?;
+                self.save_alignment(AlignmentRecord::new(
+                    &vod_id,
+                    match_id,
+                    AlignmentMethod::Detected,
+                    alignment.clone(),
+                ));
+                alignment
+            }
+        };
+        self.check_confidence(&alignment)?;
+
+        let mut manifest = self
+            .plan_manifest(
+                &events,
+                puuids,
+                valo_match,
+                &vod_id,
+                vod_interval.0,
+                alignment,
+            )
+            .await?
+            .with_previous(previous.as_ref());
+        self.save_manifest(&manifest);
+        self.cut_clips(
+            &mut manifest,
+            &events,
+            &match_video_path,
+            valo_match,
+            puuids,
+            false,
+        )
+        .await?;
+
+        if self.remove_matches {
+            std::fs::remove_file(match_video_path).ok();
+        }
+        Ok(clip_results(&manifest, &events))
+    }
+
+    /// Discovers the matches of the tracked players in `source` that pass the match filters.
+    pub async fn discover(&self, source: &VodSource) -> Result<MatchList> {
+        let puuids = self.resolve_puuids().await?;
+        let vod_id = source.id();
+        let vod_interval = source.interval().await?;
+        let matches = self
+            .discover_matches(&puuids, &vod_id, vod_interval, self.force)
+            .await?
+            .into_iter()
+            .filter(|valo_match| self.skip_reason(valo_match).is_none())
+            .collect();
+        Ok(MatchList {
+            vod_id,
+            start: vod_interval.0.format(&Rfc3339).unwrap(),
+            end: vod_interval.1.format(&Rfc3339).unwrap(),
+            puuids,
+            matches,
+        })
+    }
+
+    /// Stores the part of `source` covering `valo_match` in the scratch directory.
+    pub async fn download_match(
+        &self,
+        source: &VodSource,
+        vod_interval: (OffsetDateTime, OffsetDateTime),
+        valo_match: &MatchDetailsV1,
+    ) -> Result<PathBuf> {
+        let match_video_path = ArtifactPaths::new(&self.config.scratch_dir, source.id())
+            .video(valo_match.match_info.match_id);
+        let MatchWindow { start, end, .. } = video::get_match_interval(vod_interval, valo_match)?;
+        let (download_source, download_path) = (source.clone(), match_video_path.clone());
+        run_limited(&self.downloads, move || {
+            download_source.save_video(&download_path, start, end)
+        })
+        .await?;
+        Ok(match_video_path)
+    }
+
+    /// Runs the detectors of all anchors with a model on the match video cut out of the VOD
+    /// at `window`. Scores stored by an earlier run with the same video, detector and
+    /// sampling are reused, see [`detections`]. With [`Config::early_stop`] the detector of
+    /// each anchor stops once it has found as many events as there are `match_anchors`.
+    pub async fn detect(
+        &self,
+        match_video_path: &Path,
+        valo_match: &MatchDetailsV1,
+        window: MatchWindow,
+        match_anchors: &Anchors,
+    ) -> Result<Timeline> {
+        let min_offset = valo_match.match_info.queue_id.map_or(40000, |q| match q {
+            Queue::Deathmatch => 0,
+            Queue::Competitive => 60000,
+            _ => 40000,
+        });
+
+        let (profile_name, profile) = self.hud_profile(match_video_path).await?;
+        let mut timeline = Timeline {
+            min_offset_millis: min_offset,
+            missing_start_millis: window.missing_start.as_millis() as u64,
+            video_length_millis: local::get_duration(match_video_path)
+                .map(|length| length.as_millis() as u64)
+                .ok(),
+            frames: vec![],
+            anchor_frames: BTreeMap::new(),
+            model_hashes: BTreeMap::new(),
+            hud_profile: profile_name,
+        };
+
+        let fingerprint = detections::video_fingerprint(match_video_path)
+            .map_err(|e| warn!("Failed to fingerprint match video: {}", e))
+            .ok();
+        let threshold = self.config.primary_debounce().threshold;
+        let bar = BarSlot::acquire(&self.bars);
+        for kind in AnchorKind::ALL {
+            let (Some(model_path), Some(crop)) =
+                (profile.model(&self.config, kind), profile.filter(kind))
+            else {
+                continue;
+            };
+            let (video_path, model_path, backend, seek, position, sampling) = (
+                match_video_path.to_path_buf(),
+                model_path.to_path_buf(),
+                self.config.detector,
+                timeline.seek_millis(),
+                bar.position,
+                self.config.sampling.clone(),
+            );
+            let early_stop = match self.config.early_stop {
+                true => match_anchors.get(&kind).map(|anchors| EarlyStop {
+                    max_events: anchors.len(),
+                    debounce: self.config.primary_debounce(),
+                }),
+                false => None,
+            };
+            let model_hash = self.model_hash(&model_path);
+            let key = fingerprint
+                .clone()
+                .zip(model_hash.clone())
+                .map(|(video, model_hash)| DetectionKey {
+                    kind,
+                    video,
+                    backend,
+                    model_hash,
+                    crop: crop.clone(),
+                    seek_millis: seek,
+                    sampling: sampling.clone(),
+                    threshold,
+                    early_stop: early_stop.clone(),
+                });
+            let scores = match key
+                .as_ref()
+                .and_then(|key| detections::load(match_video_path, key))
+            {
+                Some(scores) => scores,
+                None => {
+                    let scores = run_limited(&self.inference, move || {
+                        let detector = detector::load(backend, &model_path)?;
+                        video::detect_anchor_scores(
+                            &video_path,
+                            detector.as_ref(),
+                            &crop,
+                            seek,
+                            position,
+                            threshold,
+                            early_stop.as_ref(),
+                            &sampling,
+                        )
+                    })
+                    .await?;
+                    if let Some(key) = key {
+                        detections::save(match_video_path, key, scores.clone());
+                    }
+                    scores
+                }
+            };
+            if let Some(hash) = model_hash {
+                timeline.model_hashes.insert(kind, hash);
+            }
+            match kind {
+                AnchorKind::Kill => timeline.frames = scores,
+                _ => {
+                    timeline.anchor_frames.insert(kind, scores);
+                }
+            }
+        }
+        Ok(timeline)
+    }
+
+    /// Builds the events of the tracked players and the manifest of their clips cut with
+    /// `alignment`.
+    pub async fn plan_clips(
+        &self,
+        puuids: &HashSet<String>,
+        valo_match: &MatchDetailsV1,
+        vod_id: &str,
+        vod_start: OffsetDateTime,
+        alignment: Alignment,
+    ) -> Result<Manifest> {
+        let events = self.select_events(puuids, valo_match).await;
+        self.plan_manifest(&events, puuids, valo_match, vod_id, vod_start, alignment)
+            .await
+    }
+
+    /// Cuts the clips of `manifest` that are not done yet, or all of them with `recut`, and
+    /// saves the manifest.
+    pub async fn cut(
+        &self,
+        manifest: &mut Manifest,
+        valo_match: &MatchDetailsV1,
+        puuids: &HashSet<String>,
+        match_video_path: &Path,
+        recut: bool,
+    ) -> Result<Vec<ClipResult>> {
+        let events = events::build_events(valo_match);
+        let result = self
+            .cut_clips(
+                manifest,
+                &events,
+                match_video_path,
+                valo_match,
+                puuids,
+                recut,
+            )
+            .await;
+        self.save_manifest(manifest);
+        result.map(|_| clip_results(manifest, &events))
+    }
+
+    /// Builds the manifest of the clips of `events` cut with `alignment`, all still planned.
+    async fn plan_manifest(
+        &self,
+        events: &[Event],
+        puuids: &HashSet<String>,
+        valo_match: &MatchDetailsV1,
+        vod_id: &str,
+        vod_start: OffsetDateTime,
+        alignment: Alignment,
+    ) -> Result<Manifest> {
+        let mut clips = vec![];
+        for event in events {
+            // events before the start of the match video can't be cut
+            let (start, status) = match video_interval(&alignment, event) {
+                Ok((start, _)) => (start, ClipStatus::Planned),
+                Err(_) => (Duration::ZERO, ClipStatus::OutsideVideo),
+            };
+            let (category, path) = self
+                .clip_path(event, puuids, valo_match, vod_id, vod_start, start)
+                .await?;
+            clips.push(ManifestClip {
+                event_hash: manifest::event_hash(event, *CLIP_PADDING),
+                category,
+                path,
+                offset_millis: clip_offset(&alignment, event),
+                refined_offset_millis: None,
+                status,
+            });
+        }
+        let missing_start = vod_start - match_date(valo_match)?;
+        Ok(Manifest {
+            vod_id: vod_id.to_string(),
+            match_id: valo_match.match_info.match_id,
+            alignment,
+            missing_start_millis: missing_start.whole_milliseconds().max(0) as u64,
+            clips,
+        })
+    }
+
+    /// Cuts the clips of `manifest` from the match video. `events` has to contain the events
+    /// of the clips, they are matched by [`manifest::event_hash`].
+    async fn cut_clips(
+        &self,
+        manifest: &mut Manifest,
+        events: &[Event],
+        match_video_path: &Path,
+        valo_match: &MatchDetailsV1,
+        puuids: &HashSet<String>,
+        recut: bool,
+    ) -> Result<()> {
+        self.check_confidence(&manifest.alignment)?;
+        let events = events_by_hash(events);
+        let alignment = manifest.alignment.clone();
+        // the match video starts at the VOD start if the match started earlier
+        let video_date =
+            match_date(valo_match)? + Duration::from_millis(manifest.missing_start_millis);
+        let video_length = local::get_duration(match_video_path)
+            .map_err(|e| Error::Cut(format!("Failed to probe match video: {e}")))?;
+        let game_mode = game_mode(valo_match);
+        // only the kills of the tracked players have a banner to refine on
+        let kill_times = anchors::match_anchors(valo_match, puuids)
+            .map(|anchors| {
+                anchors[&AnchorKind::Kill]
+                    .iter()
+                    .map(|kill| kill.as_millis() as u64)
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+        let refiner = match self.config.refine_events {
+            true => self
+                .kill_refiner(match_video_path)
+                .await
+                .map_err(|e| warn!("Failed to set up kill refinement: {}", e))
+                .ok(),
+            false => None,
+        };
+
+        let mut cuts = vec![];
+        for (i, clip) in manifest.clips.iter_mut().enumerate() {
+            if matches!(clip.status, ClipStatus::Stale | ClipStatus::OutsideVideo)
+                || (clip.status == ClipStatus::Done && !recut)
+            {
+                continue;
+            }
+            let Some(event) = events.get(&clip.event_hash) else {
+                warn!("No event for clip {}", clip.path.display());
+                continue;
+            };
+
+            let (start, end) = video_interval(&alignment, event)?;
+            if end > video_length {
+                warn!(
+                    "Clip {} is after the end of the match video",
+                    clip.path.display()
+                );
+                clip.status = ClipStatus::OutsideVideo;
+                continue;
+            }
+            std::fs::create_dir_all(clip.path.parent().unwrap())
+                .map_err(|e| Error::Cut(format!("Failed to create clip directory: {e}")))?;
+            let refiner = refiner.as_ref().filter(|_| {
+                kill_times.contains(&(event.game_time_interval().0.as_millis() as u64))
+            });
+            let offset = clip_offset(&alignment, event);
+            let mut metadata = Metadata {
+                track: offset.to_string(),
+                title: clip.category.to_string(),
+                album: valo_match.match_info.match_id.to_string(),
+                episode_id: valo_match.match_info.season_id.to_string(),
+                description: serde_json::to_string(event).unwrap(),
+                genre: game_mode.to_string(),
+                comment: format!(
+                    "offset_ms={},confidence={:.2},inliers={},residual_ms={:.0},pieces={}",
+                    offset,
+                    alignment.confidence,
+                    alignment.inliers,
+                    alignment.residual_ms,
+                    alignment.pieces.len().max(1)
+                ),
+            };
+            let (video_path, out_path) = (match_video_path.to_path_buf(), clip.path.clone());
+            cuts.push(async move {
+                let refinement = match refiner {
+                    Some(refiner) => self.refine_kill(refiner, &video_path, start).await,
+                    None => None,
+                };
+                let refinement_millis = refinement.unwrap_or(0);
+                let (start, end) = (
+                    shift(start, refinement_millis).saturating_sub(CLIP_PADDING.0),
+                    (shift(end, refinement_millis) + CLIP_PADDING.1).min(video_length),
+                );
+                let refined_offset = refinement.map(|refinement| offset + refinement);
+                if let Some(refinement) = refinement {
+                    metadata.track = (offset + refinement).to_string();
+                    metadata.comment += &format!(",refined_ms={refinement}");
+                }
+                let cut = run_limited(&self.cuts, move || {
+                    video::split_video(&video_path, &out_path, start, end, true, Some(metadata))
+                })
+                .await;
+                (i, start, refined_offset, cut)
+            });
+        }
+
+        let bar = BarSlot::acquire(&self.bars);
+        let mut pb = tqdm!(
+            total = cuts.len(),
+            desc = "Saving clips",
+            position = bar.position
+        );
+        let mut cuts = futures::stream::iter(cuts).buffered(self.config.concurrency.cuts.max(1));
+        while let Some((i, start, refined_offset, cut)) = cuts.next().await {
+            pb.update(1).ok();
+            let clip = &mut manifest.clips[i];
+            clip.refined_offset_millis = refined_offset;
+            let file_time = FileTime::from_system_time(SystemTime::from(video_date + start));
+            let saved = cut.map_err(|e| Error::Cut(e.to_string())).and_then(|_| {
+                set_file_times(&clip.path, file_time, file_time, file_time)
+                    .map_err(|e| Error::Metadata(format!("Failed to set file times: {e}")))
+            });
+            clip.status = match saved {
+                Ok(()) => ClipStatus::Done,
+                Err(e) => {
+                    error!("Failed to save clip {}: {}", clip.path.display(), e);
+                    ClipStatus::Failed
+                }
+            };
+            self.save_manifest(manifest);
+        }
+        Ok(())
+    }
+
+    /// The kill detector of the HUD profile of the match video, for [`Pipeline::refine_kill`].
+    async fn kill_refiner(&self, match_video_path: &Path) -> Result<KillRefiner> {
+        let (name, profile) = self.hud_profile(match_video_path).await?;
+        let model_path = profile
+            .model(&self.config, AnchorKind::Kill)
+            .ok_or_else(|| Error::Config("No kill detection model".to_string()))?
+            .to_path_buf();
+        let crop = profile
+            .filter(AnchorKind::Kill)
+            .ok_or_else(|| Error::Config(format!("HUD profile {name} has no kill area")))?;
+        Ok(KillRefiner {
+            crop,
+            latency_millis: self.detector_latency(self.model_hash(&model_path).as_deref(), &name),
+            model_path,
+        })
+    }
+
+    /// How far the kill banner closest to `expected` in the match video is from where the
+    /// alignment puts it, detected at a higher frame rate within [`REFINEMENT_WINDOW`]. `None`
+    /// if there is no banner.
+    async fn refine_kill(
+        &self,
+        refiner: &KillRefiner,
+        match_video_path: &Path,
+        expected: Duration,
+    ) -> Option<i64> {
+        let banner = shift(expected, refiner.latency_millis);
+        let window_start = banner.saturating_sub(REFINEMENT_WINDOW);
+        let (video_path, model_path, backend, crop, threshold) = (
+            match_video_path.to_path_buf(),
+            refiner.model_path.clone(),
+            self.config.detector,
+            refiner.crop.clone(),
+            self.config.primary_debounce().threshold,
+        );
+        let onsets = run_limited(&self.inference, move || {
+            let detector = detector::load(backend, &model_path)?;
+            video::detect_kill_onsets(
+                &video_path,
+                detector.as_ref(),
+                &crop,
+                window_start,
+                2 * REFINEMENT_WINDOW,
+                threshold,
+            )
+        })
+        .await
+        .map_err(|e| warn!("Failed to refine kill at {:?}: {}", expected, e))
+        .ok()?;
+        onsets
+            .iter()
+            .map(|onset| onset.as_millis() as i64 - banner.as_millis() as i64)
+            .min_by_key(|difference| difference.abs())
+    }
+
+    /// The stored alignment of the match to reuse. Manual alignments are used even with
+    /// `force`, detected ones are found again.
+    pub fn known_alignment(&self, vod_id: &str, match_id: Uuid) -> Option<Alignment> {
+        self.state
+            .load_alignment(vod_id, match_id)
+            .filter(|record| record.method == AlignmentMethod::Manual || !self.force)
+            .map(|record| record.alignment)
+    }
+
+    /// Stores `offset_millis`, the position of game time zero in the match video, as the
+    /// manual alignment of the match. Later runs use it instead of detecting one, and try the
+    /// match again if it failed.
+    pub fn set_manual_offset(
+        &self,
+        vod_id: &str,
+        match_id: Uuid,
+        offset_millis: i64,
+    ) -> Result<AlignmentRecord> {
+        let record = AlignmentRecord::new(
+            vod_id,
+            match_id,
+            AlignmentMethod::Manual,
+            Alignment::manual(offset_millis),
+        );
+        self.state
+            .save_alignment(&record)
+            .map_err(|e| Error::Alignment(format!("Failed to save alignment: {e}")))?;
+        self.state
+            .unmark_failed(vod_id, match_id)
+            .map_err(|e| Error::Alignment(format!("Failed to remove failed marker: {e}")))?;
+        Ok(record)
+    }
+
+    /// Refuses alignments below [`Config::min_confidence`].
+    fn check_confidence(&self, alignment: &Alignment) -> Result<()> {
+        if alignment.confidence < self.config.min_confidence {
+            return Err(Error::Alignment(format!(
+                "Confidence {:.2} is below {:.2}",
+                alignment.confidence, self.config.min_confidence
+            )));
+        }
+        Ok(())
+    }
+
+    fn save_alignment(&self, record: AlignmentRecord) {
+        if let Err(e) = self.state.save_alignment(&record) {
+            warn!("Failed to save alignment of {}: {}", record.match_id, e);
+        }
+    }
+
+    fn save_manifest(&self, manifest: &Manifest) {
+        if let Err(e) = self.state.save_manifest(manifest) {
+            warn!("Failed to save manifest of {}: {}", manifest.match_id, e);
+        }
+    }
+
+    /// Writes the match list of a run for the staged commands and calibration. Matches of an
+    /// earlier list that were not discovered again, e.g. because they are processed, are kept.
+    fn save_match_list(&self, mut match_list: MatchList) {
+        let path = ArtifactPaths::new(&self.config.scratch_dir, &match_list.vod_id).match_list();
+        if let Ok(previous) = stages::load::<MatchList>(&path, Error::Discovery) {
+            let known = match_list.match_ids();
+            match_list.matches.extend(
+                previous
+                    .matches
+                    .into_iter()
+                    .filter(|m| !known.contains(&m.match_info.match_id)),
+            );
+            match_list.puuids.extend(previous.puuids);
+        }
+        if let Err(e) = stages::save(&path, &match_list, Error::Discovery) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// The kill detector used to refine clips of one match video.
+struct KillRefiner {
+    model_path: PathBuf,
+    /// The [`HudProfile::filter`] of kills.
+    crop: String,
+    latency_millis: i64,
+}
+
+/// Finds the offset between game time and match video time from the detected anchors of all
+/// kinds in the timeline, moved back by the `latency` of their detector. Anchor events are
+/// extracted with each of `debounces`, the alignment with the highest confidence wins.
+pub fn align(
+    timeline: &Timeline,
+    match_anchors: &Anchors,
+    debounces: &[Debounce],
+    latency: impl Fn(AnchorKind) -> i64,
+) -> Result<Alignment> {
+    let match_anchors = match_anchors
+        .iter()
+        .filter(|(kind, _)| timeline.frames(**kind).is_some())
+        .map(|(kind, times)| (*kind, anchors_in_video(timeline, times)))
+        .collect::<Anchors>();
+    let mut best: Option<Alignment> = None;
+    let mut detection_error = None;
+    let default_debounces = [Debounce::default()];
+    let debounces = match debounces.is_empty() {
+        true => &default_debounces[..],
+        false => debounces,
+    };
+    for debounce in debounces {
+        let detected_anchors = match_anchors
+            .keys()
+            .map(|kind| {
+                let frames = timeline.frames(*kind).unwrap();
+                let latency = latency(*kind);
+                let detected = video::detect_kill_events(timeline.seek_millis(), debounce, frames)
+                    .into_iter()
+                    .map(|time| shift(time, -latency))
+                    .sorted()
+                    .collect::<Vec<_>>();
+                (*kind, detected)
+            })
+            .collect::<Anchors>();
+        if let Err(e) = check_detected_anchors(&detected_anchors, &match_anchors) {
+            detection_error = Some(e);
+            continue;
+        }
+        let tracks = match_anchors
+            .iter()
+            .map(|(kind, in_match)| offset::Track {
+                detected: &detected_anchors[kind],
+                in_match,
+            })
+            .collect::<Vec<_>>();
+        if let Some(alignment) = offset::align(&tracks, timeline.offsets()) {
+            if best
+                .as_ref()
+                .is_none_or(|best| alignment.confidence > best.confidence)
+            {
+                best = Some(alignment);
+            }
+        }
+    }
+    match (best, detection_error) {
+        (Some(alignment), _) => Ok(alignment),
+        (None, Some(e)) => Err(e),
+        (None, None) => Err(Error::Alignment(
+            "No offset matches the detected kills".to_string(),
+        )),
+    }
+}
+
+/// The match anchors that can be in the match video, leaving out those before the VOD
+/// started or after it ended.
+fn anchors_in_video(timeline: &Timeline, match_anchors: &[Duration]) -> Vec<Duration> {
+    let missing_start = Duration::from_millis(timeline.missing_start_millis);
+    let end = timeline
+        .video_length_millis
+        .map_or(Duration::MAX, |length| {
+            missing_start + Duration::from_millis(length)
+        });
+    match_anchors
+        .iter()
+        .copied()
+        .filter(|anchor| (missing_start..end).contains(anchor))
+        .collect()
+}
+
+/// The interval of `event` in the match video.
+fn video_interval(alignment: &Alignment, event: &Event) -> Result<(Duration, Duration)> {
+    let (start, end) = event.game_time_interval();
+    match (alignment.map(start), alignment.map(end)) {
+        (Some(start), Some(end)) => Ok((start, end.max(start))),
+        _ => Err(Error::Alignment(format!(
+            "Event at {start:?} is before the video start"
+        ))),
+    }
+}
+
+/// Moves `time` by `millis`, not before zero.
+fn shift(time: Duration, millis: i64) -> Duration {
+    Duration::from_millis((time.as_millis() as i64 + millis).max(0) as u64)
+}
+
+/// The offset used for the clip of `event`.
+fn clip_offset(alignment: &Alignment, event: &Event) -> i64 {
+    alignment.offset_at(event.game_time_interval().0.as_millis() as u64)
+}
+
+fn events_by_hash(events: &[Event]) -> HashMap<String, &Event> {
+    events
+        .iter()
+        .map(|event| (manifest::event_hash(event, *CLIP_PADDING), event))
+        .collect()
+}
+
+/// The done clips of `manifest` with their events.
+fn clip_results(manifest: &Manifest, events: &[Event]) -> Vec<ClipResult> {
+    let events = events_by_hash(events);
+    manifest
+        .clips
+        .iter()
+        .filter(|clip| clip.status == ClipStatus::Done)
+        .filter_map(|clip| {
+            Some(ClipResult {
+                vod_id: manifest.vod_id.clone(),
+                match_id: manifest.match_id,
+                category: clip.category.clone(),
+                path: clip.path.clone(),
+                event: (*events.get(&clip.event_hash)?).clone(),
+            })
+        })
+        .collect()
+}
+
+/// Runs the blocking `f` on the blocking thread pool once a permit of `limit` is available.
+async fn run_limited<T: Send + 'static>(
+    limit: &Semaphore,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> T {
+    let _permit = limit.acquire().await.expect("Semaphore is never closed");
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Hands out terminal lines to progress bars of concurrent jobs so they don't overwrite
+/// each other.
+#[derive(Debug, Default)]
+struct BarPositions(Mutex<Vec<bool>>);
+
+/// A line of [`BarPositions`] that is given back when dropped.
+struct BarSlot<'a> {
+    bars: &'a BarPositions,
+    position: u16,
+}
+
+impl<'a> BarSlot<'a> {
+    fn acquire(bars: &'a BarPositions) -> Self {
+        let mut used = bars.0.lock().unwrap();
+        let position = match used.iter().position(|used| !used) {
+            Some(free) => free,
+            None => {
+                used.push(false);
+                used.len() - 1
+            }
+        };
+        used[position] = true;
+        Self {
+            bars,
+            position: position as u16,
+        }
+    }
+}
+
+impl Drop for BarSlot<'_> {
+    fn drop(&mut self) {
+        self.bars.0.lock().unwrap()[self.position as usize] = false;
+    }
+}
+
+/// Rejects detector output with too few anchors over all kinds to be aligned reliably.
+fn check_detected_anchors(detected: &Anchors, in_match: &Anchors) -> Result<()> {
+    let detected = detected.values().map(Vec::len).sum::<usize>();
+    let in_match = in_match.values().map(Vec::len).sum::<usize>();
+    if detected == 0 {
+        return Err(Error::Detection("No detected anchors found".to_string()));
+    }
+    if detected < in_match / 2 {
+        return Err(Error::Detection(format!(
+            "Fewer detected anchors than match anchors: Detected: {detected}, Match: {in_match}"
+        )));
+    }
+    Ok(())
+}
+
+fn match_date(valo_match: &MatchDetailsV1) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(valo_match.match_info.game_start_millis as i64 / 1000)
+        .map_err(|e| Error::Metadata(format!("Invalid match start: {e}")))
+}
+
+fn game_mode(valo_match: &MatchDetailsV1) -> String {
+    valo_match
+        .match_info
+        .queue_id
+        .map_or("other".to_string(), |q| q.to_string())
+}
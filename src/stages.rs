@@ -0,0 +1,512 @@
+//! The pipeline stages run one at a time, each reading the artifacts of the previous stages
+//! from the scratch directory and writing its own.
+
+use crate::anchors::{self, AnchorKind};
+use crate::bench;
+use crate::calibration;
+use crate::error::{Error, Result};
+use crate::local;
+use crate::manifest::Manifest;
+use crate::offset::{self, Alignment, AlignmentMethod, AlignmentRecord};
+use crate::pipeline::{self, ClipResult, Pipeline, VodSource};
+use crate::video;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
+
+/// The matches discovered in a VOD, written by the `discover` stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchList {
+    pub vod_id: String,
+    /// RFC 3339 start and end of the VOD.
+    pub start: String,
+    pub end: String,
+    pub puuids: HashSet<String>,
+    pub matches: Vec<MatchDetailsV1>,
+}
+
+impl MatchList {
+    pub fn vod_start(&self) -> Result<OffsetDateTime> {
+        OffsetDateTime::parse(&self.start, &Rfc3339)
+            .map_err(|e| Error::Discovery(format!("Invalid VOD start {}: {e}", self.start)))
+    }
+
+    pub fn vod_interval(&self) -> Result<(OffsetDateTime, OffsetDateTime)> {
+        let end = OffsetDateTime::parse(&self.end, &Rfc3339)
+            .map_err(|e| Error::Discovery(format!("Invalid VOD end {}: {e}", self.end)))?;
+        Ok((self.vod_start()?, end))
+    }
+
+    pub fn get(&self, match_id: Uuid) -> Result<&MatchDetailsV1> {
+        self.matches
+            .iter()
+            .find(|m| m.match_info.match_id == match_id)
+            .ok_or_else(|| Error::Discovery(format!("Match {match_id} is not in the match list")))
+    }
+
+    pub fn match_ids(&self) -> Vec<Uuid> {
+        self.matches.iter().map(|m| m.match_info.match_id).collect()
+    }
+}
+
+/// Timestamps in seconds and detector scores of the frames of a match video.
+pub type Scores = Vec<(f32, f32)>;
+
+/// The anchor detector output for a match video, written by the `detect` stage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timeline {
+    /// Earliest position of game time zero in a complete match video.
+    pub min_offset_millis: u64,
+    /// How much of the match is missing at the start of the match video.
+    #[serde(default)]
+    pub missing_start_millis: u64,
+    /// Length of the match video.
+    #[serde(default)]
+    pub video_length_millis: Option<u64>,
+    /// Timestamp in seconds since [`Timeline::seek_millis`] and the score of the kill
+    /// detector, see [`KillDetector::scores`](crate::detector::KillDetector::scores).
+    pub frames: Scores,
+    /// Like `frames`, for the other anchors with a detection model.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub anchor_frames: BTreeMap<AnchorKind, Scores>,
+    /// Content hashes of the detection models the frames were detected with.
+    #[serde(default)]
+    pub model_hashes: BTreeMap<AnchorKind, String>,
+    /// Name of the HUD profile the frames were cut out with.
+    pub hud_profile: String,
+}
+
+impl Timeline {
+    /// Position in the match video where detection started.
+    pub fn seek_millis(&self) -> u64 {
+        self.min_offset_millis
+            .saturating_sub(self.missing_start_millis)
+    }
+
+    /// The detector output of `kind`, if it was detected.
+    pub fn frames(&self, kind: AnchorKind) -> Option<&Scores> {
+        match kind {
+            AnchorKind::Kill => Some(&self.frames),
+            _ => self.anchor_frames.get(&kind),
+        }
+    }
+
+    /// The possible offsets between game time and match video time.
+    pub fn offsets(&self) -> std::ops::Range<i64> {
+        let missing = self.missing_start_millis as i64;
+        self.min_offset_millis as i64 - missing..offset::MAX_OFFSET_MILLIS - missing
+    }
+}
+
+/// Where the stage artifacts of a VOD and its matches are stored in the scratch directory.
+#[derive(Debug, Clone)]
+pub struct ArtifactPaths {
+    scratch_dir: PathBuf,
+    vod_id: String,
+}
+
+impl ArtifactPaths {
+    pub fn new(scratch_dir: impl Into<PathBuf>, vod_id: impl Into<String>) -> Self {
+        Self {
+            scratch_dir: scratch_dir.into(),
+            vod_id: vod_id.into(),
+        }
+    }
+
+    pub fn match_list(&self) -> PathBuf {
+        self.scratch_dir
+            .join(format!("{}.matches.json", self.vod_id))
+    }
+
+    pub fn video(&self, match_id: Uuid) -> PathBuf {
+        self.scratch_dir
+            .join(format!("{}-{}.mkv", self.vod_id, match_id))
+    }
+
+    pub fn timeline(&self, match_id: Uuid) -> PathBuf {
+        self.scratch_dir
+            .join(format!("{}-{}.timeline.json", self.vod_id, match_id))
+    }
+
+    pub fn alignment(&self, match_id: Uuid) -> PathBuf {
+        self.scratch_dir
+            .join(format!("{}-{}.alignment.json", self.vod_id, match_id))
+    }
+
+    pub fn events(&self, match_id: Uuid) -> PathBuf {
+        self.scratch_dir
+            .join(format!("{}-{}.events.json", self.vod_id, match_id))
+    }
+}
+
+/// Reads the artifact at `path`. `stage` wraps the failure reason.
+pub fn load<T: DeserializeOwned>(path: &Path, stage: fn(String) -> Error) -> Result<T> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| stage(format!("Failed to read {}: {e}", path.display())))?;
+    serde_json::from_str(&content)
+        .map_err(|e| stage(format!("Failed to parse {}: {e}", path.display())))
+}
+
+/// Writes the artifact to `path`. `stage` wraps the failure reason.
+pub fn save<T: Serialize>(path: &Path, artifact: &T, stage: fn(String) -> Error) -> Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| std::fs::write(path, serde_json::to_string_pretty(artifact).unwrap()))
+        .map_err(|e| stage(format!("Failed to write {}: {e}", path.display())))
+}
+
+fn paths(pipeline: &Pipeline, source: &VodSource) -> ArtifactPaths {
+    ArtifactPaths::new(&pipeline.config().scratch_dir, source.id())
+}
+
+fn manual_alignment(pipeline: &Pipeline, vod_id: &str, match_id: Uuid) -> Option<Alignment> {
+    pipeline
+        .state()
+        .load_alignment(vod_id, match_id)
+        .filter(|record| record.method == AlignmentMethod::Manual)
+        .map(|record| record.alignment)
+}
+
+/// Loads the match list written by [`discover`].
+pub fn match_list(pipeline: &Pipeline, source: &VodSource) -> Result<MatchList> {
+    load(&paths(pipeline, source).match_list(), Error::Discovery)
+}
+
+/// Discovers the matches of `source` and writes the match list.
+pub async fn discover(pipeline: &Pipeline, source: &VodSource) -> Result<MatchList> {
+    let match_list = pipeline.discover(source).await?;
+    save(
+        &paths(pipeline, source).match_list(),
+        &match_list,
+        Error::Discovery,
+    )?;
+    Ok(match_list)
+}
+
+/// Stores the match video of `match_id`.
+pub async fn download(pipeline: &Pipeline, source: &VodSource, match_id: Uuid) -> Result<PathBuf> {
+    let match_list = match_list(pipeline, source)?;
+    pipeline
+        .download_match(
+            source,
+            match_list.vod_interval()?,
+            match_list.get(match_id)?,
+        )
+        .await
+}
+
+/// Runs the anchor detectors on the match video of `match_id` and writes the timeline.
+pub async fn detect(pipeline: &Pipeline, source: &VodSource, match_id: Uuid) -> Result<Timeline> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let valo_match = match_list.get(match_id)?;
+    let window = video::get_match_interval(match_list.vod_interval()?, valo_match)?;
+    let match_anchors = anchors::match_anchors(valo_match, &match_list.puuids)?;
+    let timeline = pipeline
+        .detect(&paths.video(match_id), valo_match, window, &match_anchors)
+        .await?;
+    save(&paths.timeline(match_id), &timeline, Error::Detection)?;
+    Ok(timeline)
+}
+
+/// Aligns the timeline of `match_id` with the match, writes the [`Alignment`] and stores it
+/// as the detected alignment of the match unless it has a manual one.
+pub fn align(pipeline: &Pipeline, source: &VodSource, match_id: Uuid) -> Result<Alignment> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let timeline: Timeline = load(&paths.timeline(match_id), Error::Alignment)?;
+    let match_anchors = anchors::match_anchors(match_list.get(match_id)?, &match_list.puuids)?;
+    let alignment = pipeline::align(
+        &timeline,
+        &match_anchors,
+        &pipeline.config().debounce,
+        |kind| pipeline.timeline_latency(&timeline, kind),
+    )?;
+    save(&paths.alignment(match_id), &alignment, Error::Alignment)?;
+    if manual_alignment(pipeline, &match_list.vod_id, match_id).is_none() {
+        let record = AlignmentRecord::new(
+            &match_list.vod_id,
+            match_id,
+            AlignmentMethod::Detected,
+            alignment.clone(),
+        );
+        pipeline
+            .state()
+            .save_alignment(&record)
+            .map_err(|e| Error::Alignment(format!("Failed to save alignment: {e}")))?;
+    }
+    Ok(alignment)
+}
+
+/// Builds the events of `match_id` with the manual alignment of the match, or else the written
+/// one, and writes them as a manifest of planned clips.
+pub async fn events(pipeline: &Pipeline, source: &VodSource, match_id: Uuid) -> Result<Manifest> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let alignment = match manual_alignment(pipeline, &match_list.vod_id, match_id) {
+        Some(alignment) => alignment,
+        None => load(&paths.alignment(match_id), Error::Alignment)?,
+    };
+    let manifest = pipeline
+        .plan_clips(
+            &match_list.puuids,
+            match_list.get(match_id)?,
+            &match_list.vod_id,
+            match_list.vod_start()?,
+            alignment,
+        )
+        .await?;
+    save(&paths.events(match_id), &manifest, Error::Metadata)?;
+    Ok(manifest)
+}
+
+/// Cuts the clips of the written events of `match_id`. Clips that already exist are only cut
+/// again with `recut`.
+pub async fn cut(
+    pipeline: &Pipeline,
+    source: &VodSource,
+    match_id: Uuid,
+    recut: bool,
+) -> Result<Vec<ClipResult>> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let manifest: Manifest = load(&paths.events(match_id), Error::Cut)?;
+    let previous = pipeline.state().load_manifest(&match_list.vod_id, match_id);
+    let mut manifest = manifest.with_previous(previous.as_ref());
+    pipeline
+        .cut(
+            &mut manifest,
+            match_list.get(match_id)?,
+            &match_list.puuids,
+            &paths.video(match_id),
+            recut,
+        )
+        .await
+}
+
+/// Writes an alignment fixture of `match_id` to `dir`, taking its manual alignment as correct.
+/// Only the kill times of the written timeline and the match are kept.
+pub fn fixture(
+    pipeline: &Pipeline,
+    source: &VodSource,
+    match_id: Uuid,
+    dir: &Path,
+) -> Result<PathBuf> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let timeline: Timeline = load(&paths.timeline(match_id), Error::Alignment)?;
+    let expected = manual_alignment(pipeline, &match_list.vod_id, match_id)
+        .ok_or_else(|| Error::Alignment("No manual alignment to take as correct".to_string()))?;
+    let match_anchors = anchors::match_anchors(match_list.get(match_id)?, &match_list.puuids)?;
+    let offsets = timeline.offsets();
+    let case = bench::Case {
+        name: match_id.to_string(),
+        in_match_millis: to_millis(&match_anchors[&AnchorKind::Kill]),
+        detected_millis: to_millis(&video::detect_kill_events(
+            timeline.seek_millis(),
+            &pipeline.config().primary_debounce(),
+            &timeline.frames,
+        )),
+        min_offset_millis: offsets.start,
+        max_offset_millis: offsets.end,
+        // the aligner finds the offset before the detector latency is removed
+        expected: bench::Expected::new(
+            expected.shifted(pipeline.timeline_latency(&timeline, AnchorKind::Kill)),
+        ),
+    };
+    let path = dir.join(format!("{match_id}.json"));
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::Alignment(format!("Failed to create {}: {e}", dir.display())))?;
+    save(&path, &case, Error::Alignment)?;
+    Ok(path)
+}
+
+fn to_millis(times: &[std::time::Duration]) -> Vec<u64> {
+    times.iter().map(|t| t.as_millis() as u64).collect()
+}
+
+/// The lags of the detection models by model hash and HUD profile, measured on the matches of
+/// `source` whose manual alignment is taken as correct. Frames of unknown models are left out.
+pub fn calibration_samples(
+    pipeline: &Pipeline,
+    source: &VodSource,
+) -> Result<BTreeMap<(String, String), Vec<i64>>> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let debounce = pipeline.config().primary_debounce();
+    let mut samples = BTreeMap::<(String, String), Vec<i64>>::new();
+    for match_id in match_list.match_ids() {
+        let Some(alignment) = manual_alignment(pipeline, &match_list.vod_id, match_id) else {
+            continue;
+        };
+        let Ok(timeline) = load::<Timeline>(&paths.timeline(match_id), Error::Alignment) else {
+            continue;
+        };
+        let match_anchors = anchors::match_anchors(match_list.get(match_id)?, &match_list.puuids)?;
+        for (kind, model_hash) in &timeline.model_hashes {
+            let (Some(frames), Some(in_match)) = (timeline.frames(*kind), match_anchors.get(kind))
+            else {
+                continue;
+            };
+            let detected = video::detect_kill_events(timeline.seek_millis(), &debounce, frames);
+            samples
+                .entry((model_hash.clone(), timeline.hud_profile.clone()))
+                .or_default()
+                .extend(calibration::lags(&detected, in_match, &alignment));
+        }
+    }
+    Ok(samples)
+}
+
+/// Width and height of exported frames, the size of the kill area in a 1080p game capture.
+const EXPORT_SIZE: u32 = 200;
+/// Frames exported per kill, as time after the kill.
+const KILL_FRAME_OFFSETS_MILLIS: [i64; 3] = [250, 750, 1500];
+/// How long a kill banner stays visible after the kill.
+const BANNER_MILLIS: i64 = 4000;
+/// Time between the frames exported from stretches without kills.
+const NO_KILL_SPACING_MILLIS: i64 = 15_000;
+/// How far a frame the detector sees a banner in must be from the banners of the match kills
+/// to be exported as a hard negative, covering the detector latency.
+const HARD_NEGATIVE_MARGIN_MILLIS: i64 = 1500;
+/// Minimum time between the hard negatives of a match, so one false banner isn't exported
+/// many times.
+const HARD_NEGATIVE_SPACING_MILLIS: i64 = 2000;
+
+/// The number of frames [`export_training_data`] wrote per category.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrainingExport {
+    /// Frames shortly after the kills of the match.
+    pub kill: usize,
+    /// Frames far from every kill.
+    pub no_kill: usize,
+    /// Frames the kill detector sees a banner in, far from every kill.
+    pub hard_no_kill: usize,
+}
+
+/// Writes the kill banner area of frames of `match_id` to `dir` as training data for the kill
+/// detector, cut like the detector sees it but at the video resolution. The kills of the match
+/// are put into the video by its manual alignment, or else the written one. Frames where the
+/// written timeline disagrees with the match go to `hard_no_kill`.
+pub async fn export_training_data(
+    pipeline: &Pipeline,
+    source: &VodSource,
+    match_id: Uuid,
+    dir: &Path,
+) -> Result<TrainingExport> {
+    let match_list = match_list(pipeline, source)?;
+    let paths = paths(pipeline, source);
+    let video_path = paths.video(match_id);
+    let alignment = match manual_alignment(pipeline, &match_list.vod_id, match_id) {
+        Some(alignment) => alignment,
+        None => load(&paths.alignment(match_id), Error::Alignment)?,
+    };
+    // the frames are cut out like for detection, probing the profile if it wasn't detected
+    let timeline = load::<Timeline>(&paths.timeline(match_id), Error::Detection).ok();
+    let (_, profile) = match &timeline {
+        Some(timeline) => pipeline
+            .config()
+            .hud_profiles()
+            .into_iter()
+            .find(|(name, _)| *name == timeline.hud_profile)
+            .ok_or_else(|| {
+                Error::Config(format!("Unknown HUD profile {}", timeline.hud_profile))
+            })?,
+        None => pipeline.hud_profile(&video_path).await?,
+    };
+    let crop_filter = profile
+        .crop_filter(AnchorKind::Kill)
+        .ok_or_else(|| Error::Config("The HUD profile has no kill area".to_string()))?;
+    let filter = format!("{crop_filter},scale={EXPORT_SIZE}:{EXPORT_SIZE}");
+    let video_millis = local::get_duration(&video_path)
+        .map_err(|e| Error::Detection(format!("Failed to probe match video: {e}")))?
+        .as_millis() as i64;
+
+    let match_anchors = anchors::match_anchors(match_list.get(match_id)?, &match_list.puuids)?;
+    let kills = match_anchors
+        .get(&AnchorKind::Kill)
+        .into_iter()
+        .flatten()
+        .map(|kill| {
+            let game_millis = kill.as_millis() as u64;
+            game_millis as i64 + alignment.offset_at(game_millis)
+        })
+        .collect::<Vec<_>>();
+    let near_kill = |time: i64, margin: i64| {
+        kills
+            .iter()
+            .any(|kill| (kill - margin..=kill + BANNER_MILLIS + margin).contains(&time))
+    };
+
+    let mut frames = kills
+        .iter()
+        .flat_map(|kill| KILL_FRAME_OFFSETS_MILLIS.map(|offset| ("kill", kill + offset)))
+        .collect::<Vec<_>>();
+    frames.extend(
+        (alignment.offset_at(0).max(0)..video_millis)
+            .step_by(NO_KILL_SPACING_MILLIS as usize)
+            .filter(|time| !near_kill(*time, BANNER_MILLIS))
+            .map(|time| ("no_kill", time)),
+    );
+    if let Some(timeline) = &timeline {
+        let threshold = pipeline.config().primary_debounce().threshold;
+        let mut last = i64::MIN;
+        for (timestamp, score) in &timeline.frames {
+            let time = timeline.seek_millis() as i64 + (timestamp * 1000.) as i64;
+            if *score > threshold
+                && !near_kill(time, HARD_NEGATIVE_MARGIN_MILLIS)
+                && time - last >= HARD_NEGATIVE_SPACING_MILLIS
+            {
+                frames.push(("hard_no_kill", time));
+                last = time;
+            }
+        }
+    }
+
+    let mut export = TrainingExport::default();
+    for (category, time) in frames {
+        if !(0..video_millis).contains(&time) {
+            continue;
+        }
+        let category_dir = dir.join(category);
+        std::fs::create_dir_all(&category_dir).map_err(|e| {
+            Error::Detection(format!("Failed to create {}: {e}", category_dir.display()))
+        })?;
+        let out_path = category_dir.join(format!("{}-{match_id}-{time}.png", match_list.vod_id));
+        if let Err(e) = video::save_frame(
+            &video_path,
+            &filter,
+            std::time::Duration::from_millis(time as u64),
+            &out_path,
+        ) {
+            warn!("Failed to export {} frame at {}ms: {}", category, time, e);
+            continue;
+        }
+        match category {
+            "kill" => export.kill += 1,
+            "no_kill" => export.no_kill += 1,
+            _ => export.hard_no_kill += 1,
+        }
+    }
+    Ok(export)
+}
+
+/// Plans the clips of `match_id` again with its current alignment and cuts the ones that
+/// changed, downloading the match video first if it was removed.
+pub async fn replan(
+    pipeline: &Pipeline,
+    source: &VodSource,
+    match_id: Uuid,
+) -> Result<Vec<ClipResult>> {
+    let video_path = paths(pipeline, source).video(match_id);
+    if !video_path.exists() {
+        download(pipeline, source, match_id).await?;
+    }
+    events(pipeline, source, match_id).await?;
+    cut(pipeline, source, match_id, false).await
+}
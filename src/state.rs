@@ -0,0 +1,143 @@
+use crate::calibration::Calibration;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::offset::AlignmentRecord;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Remembers which matches of a VOD were already processed or failed, the alignment and clip
+/// manifest of each match, and the calibration of each detection model per HUD profile.
+pub trait StateStore: Debug + Send + Sync {
+    fn is_processed(&self, vod_id: &str, match_id: Uuid) -> bool;
+    fn is_failed(&self, vod_id: &str, match_id: Uuid) -> bool;
+    fn mark_processed(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()>;
+    fn mark_failed(&self, vod_id: &str, match_id: Uuid, error: &Error) -> std::io::Result<()>;
+    /// Removes the failed marker so the next run tries the match again.
+    fn unmark_failed(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()>;
+    fn load_manifest(&self, vod_id: &str, match_id: Uuid) -> Option<Manifest>;
+    fn save_manifest(&self, manifest: &Manifest) -> std::io::Result<()>;
+    fn load_alignment(&self, vod_id: &str, match_id: Uuid) -> Option<AlignmentRecord>;
+    fn save_alignment(&self, record: &AlignmentRecord) -> std::io::Result<()>;
+    fn remove_alignment(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()>;
+    fn load_calibration(&self, model_hash: &str, hud_profile: &str) -> Option<Calibration>;
+    fn save_calibration(&self, calibration: &Calibration) -> std::io::Result<()>;
+
+    fn is_marked(&self, vod_id: &str, match_id: Uuid) -> bool {
+        self.is_processed(vod_id, match_id) || self.is_failed(vod_id, match_id)
+    }
+}
+
+/// Stores markers as files in `{root}/processed` and `{root}/failed`. Failed markers contain
+/// the JSON encoded [`Error`]. Manifests, alignments and calibrations are stored as JSON in
+/// `{root}/manifests`, `{root}/alignments` and `{root}/calibrations/{hud_profile}`.
+#[derive(Debug, Clone)]
+pub struct FsStateStore {
+    root: PathBuf,
+}
+
+impl FsStateStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn marker_path(&self, kind: &str, vod_id: &str, match_id: Uuid) -> PathBuf {
+        self.root.join(kind).join(format!("{vod_id}-{match_id}"))
+    }
+
+    fn json_path(&self, kind: &str, vod_id: &str, match_id: Uuid) -> PathBuf {
+        self.root
+            .join(kind)
+            .join(format!("{vod_id}-{match_id}.json"))
+    }
+
+    fn calibration_path(&self, model_hash: &str, hud_profile: &str) -> PathBuf {
+        self.root
+            .join("calibrations")
+            .join(hud_profile)
+            .join(format!("{model_hash}.json"))
+    }
+
+    fn write_marker(&self, path: PathBuf, content: String) -> std::io::Result<()> {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, content)
+    }
+
+    fn remove_marker(&self, path: PathBuf) -> std::io::Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StateStore for FsStateStore {
+    fn is_processed(&self, vod_id: &str, match_id: Uuid) -> bool {
+        self.marker_path("processed", vod_id, match_id).exists()
+    }
+
+    fn is_failed(&self, vod_id: &str, match_id: Uuid) -> bool {
+        self.marker_path("failed", vod_id, match_id).exists()
+    }
+
+    fn mark_processed(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()> {
+        self.write_marker(
+            self.marker_path("processed", vod_id, match_id),
+            String::new(),
+        )
+    }
+
+    fn mark_failed(&self, vod_id: &str, match_id: Uuid, error: &Error) -> std::io::Result<()> {
+        self.write_marker(
+            self.marker_path("failed", vod_id, match_id),
+            serde_json::to_string_pretty(error).unwrap(),
+        )
+    }
+
+    fn unmark_failed(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()> {
+        self.remove_marker(self.marker_path("failed", vod_id, match_id))
+    }
+
+    fn load_manifest(&self, vod_id: &str, match_id: Uuid) -> Option<Manifest> {
+        let path = self.json_path("manifests", vod_id, match_id);
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save_manifest(&self, manifest: &Manifest) -> std::io::Result<()> {
+        self.write_marker(
+            self.json_path("manifests", &manifest.vod_id, manifest.match_id),
+            serde_json::to_string_pretty(manifest).unwrap(),
+        )
+    }
+
+    fn load_alignment(&self, vod_id: &str, match_id: Uuid) -> Option<AlignmentRecord> {
+        let path = self.json_path("alignments", vod_id, match_id);
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save_alignment(&self, record: &AlignmentRecord) -> std::io::Result<()> {
+        self.write_marker(
+            self.json_path("alignments", &record.vod_id, record.match_id),
+            serde_json::to_string_pretty(record).unwrap(),
+        )
+    }
+
+    fn remove_alignment(&self, vod_id: &str, match_id: Uuid) -> std::io::Result<()> {
+        self.remove_marker(self.json_path("alignments", vod_id, match_id))
+    }
+
+    fn load_calibration(&self, model_hash: &str, hud_profile: &str) -> Option<Calibration> {
+        let content =
+            std::fs::read_to_string(self.calibration_path(model_hash, hud_profile)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save_calibration(&self, calibration: &Calibration) -> std::io::Result<()> {
+        self.write_marker(
+            self.calibration_path(&calibration.model_hash, &calibration.hud_profile),
+            serde_json::to_string_pretty(calibration).unwrap(),
+        )
+    }
+}
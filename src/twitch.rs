@@ -1,3 +1,4 @@
+use crate::error::Error;
 use crate::video::format_ffmpeg_time;
 use lazy_static::lazy_static;
 use log::{debug, info};
@@ -20,6 +21,23 @@ struct ApiData<T> {
     data: T,
 }
 
+#[derive(Debug, Deserialize)]
+struct ApiPage<T> {
+    data: T,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwitchUser {
+    id: String,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 struct TwitchVideo {
@@ -28,7 +46,7 @@ struct TwitchVideo {
     duration: String,
 }
 
-pub async fn get_vod_start_end(vod_id: usize) -> (OffsetDateTime, OffsetDateTime) {
+pub async fn get_vod_start_end(vod_id: usize) -> Result<(OffsetDateTime, OffsetDateTime), Error> {
     let client = reqwest::Client::new();
     let response: ApiData<Vec<TwitchVideo>> = client
         .get(format!("https://api.twitch.tv/helix/videos?id={vod_id}"))
@@ -36,27 +54,95 @@ pub async fn get_vod_start_end(vod_id: usize) -> (OffsetDateTime, OffsetDateTime
         .bearer_auth(TWITCH_ACCESS_TOKEN.as_str())
         .send()
         .await
-        .unwrap()
+        .map_err(|e| Error::Discovery(format!("Failed to get video {vod_id}: {e}")))?
         .json()
         .await
-        .unwrap();
+        .map_err(|e| Error::Discovery(format!("Failed to parse video {vod_id}: {e}")))?;
 
     let twitch_video = response
         .data
         .into_iter()
         .find(|video| video.id == vod_id.to_string())
-        .unwrap_or_else(|| panic!("Failed to find video: {vod_id}"));
+        .ok_or_else(|| Error::Discovery(format!("Failed to find video: {vod_id}")))?;
 
     let vod_start = twitch_video.created_at.clone();
     let vod_length = parse_length(twitch_video.duration.as_str());
     let start_time = PrimitiveDateTime::parse(vod_start.as_str(), &Rfc3339)
-        .expect("Failed to parse start time")
+        .map_err(|e| Error::Discovery(format!("Failed to parse start time: {e}")))?
         .assume_offset(UtcOffset::UTC);
     let end_time = start_time + Duration::new(vod_length as u64, 0);
-    (start_time, end_time)
+    Ok((start_time, end_time))
+}
+
+/// Returns the ids of the archived VODs of `channel` created after `created_after`, newest first.
+pub async fn get_archive_vods(
+    channel: &str,
+    created_after: OffsetDateTime,
+) -> Result<Vec<usize>, Error> {
+    let client = reqwest::Client::new();
+    let users: ApiData<Vec<TwitchUser>> = get_helix(
+        &client,
+        &format!("https://api.twitch.tv/helix/users?login={channel}"),
+    )
+    .await?;
+    let user = users
+        .data
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Discovery(format!("Failed to find channel: {channel}")))?;
+
+    let mut vods = vec![];
+    let mut cursor = None;
+    loop {
+        let mut url = format!(
+            "https://api.twitch.tv/helix/videos?user_id={}&type=archive&first=100",
+            user.id
+        );
+        if let Some(cursor) = &cursor {
+            url.push_str(&format!("&after={cursor}"));
+        }
+        let page: ApiPage<Vec<TwitchVideo>> = get_helix(&client, &url).await?;
+        let mut reached_end = page.data.is_empty();
+        for video in page.data {
+            let created_at = OffsetDateTime::parse(&video.created_at, &Rfc3339)
+                .map_err(|e| Error::Discovery(format!("Failed to parse start time: {e}")))?;
+            if created_at < created_after {
+                reached_end = true;
+                break;
+            }
+            vods.push(
+                video
+                    .id
+                    .parse()
+                    .map_err(|e| Error::Discovery(format!("Invalid video id {}: {e}", video.id)))?,
+            );
+        }
+        cursor = page.pagination.cursor;
+        if reached_end || cursor.is_none() {
+            break;
+        }
+    }
+    debug!("Found {} archive VODs of {}", vods.len(), channel);
+    Ok(vods)
+}
+
+async fn get_helix<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<T, Error> {
+    client
+        .get(url)
+        .header("Client-ID", TWITCH_CLIENT_ID.as_str())
+        .bearer_auth(TWITCH_ACCESS_TOKEN.as_str())
+        .send()
+        .await
+        .map_err(|e| Error::Discovery(format!("Failed to request {url}: {e}")))?
+        .json()
+        .await
+        .map_err(|e| Error::Discovery(format!("Failed to parse {url}: {e}")))
 }
 
-pub(crate) fn download_vod(
+pub fn download_vod(
     vod_id: usize,
     out_path: &Path,
     start: Duration,
@@ -68,34 +154,7 @@ pub(crate) fn download_vod(
     }
     info!("Saving VOD: {} from {:?} to {:?}", vod_id, start, end);
     std::fs::create_dir_all(out_path.parent().unwrap())?;
-    // if let Err(e) = download_with_twitchdl(vod_id, out_path, start, end) {
-    //     warn!("Twitch-DL failed: {}", e);
-    // }
-    if !out_path.exists() {
-        download_with_ytdlp(vod_id, out_path, start, end)
-    } else {
-        Ok(ExitStatus::default())
-    }
-}
-
-fn download_with_twitchdl(
-    vod_id: usize,
-    out_path: &Path,
-    start: Duration,
-    end: Duration,
-) -> std::io::Result<ExitStatus> {
-    std::process::Command::new("twitch-dl")
-        .arg("download")
-        .arg(vod_id.to_string())
-        .arg("-q")
-        .arg("source")
-        .arg("-s")
-        .arg(format_ffmpeg_time(start, false))
-        .arg("-e")
-        .arg(format_ffmpeg_time(end, false))
-        .arg("-o")
-        .arg(out_path)
-        .status()
+    download_with_ytdlp(vod_id, out_path, start, end)
 }
 
 fn download_with_ytdlp(
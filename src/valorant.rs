@@ -3,17 +3,21 @@ use lazy_static::lazy_static;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
 use std::str::FromStr;
 use time::OffsetDateTime;
 use uuid::Uuid;
 use valorant_api_official::enums::region::Region;
 use valorant_api_official::errors::response_error::RequestError;
-use valorant_api_official::response_types::matchdetails_v1::{MatchDetailsV1, PlayerRoundKill};
+use valorant_api_official::response_types::matchdetails_v1::{
+    MatchDetailsV1, PlayerRoundKill, RoundResult,
+};
 use valorant_api_official::response_types::matchlists_v1::MatchListsEntry;
 use valorant_api_official::utils::credentials_manager::CredentialsManager;
 
+use crate::error::Error;
+use crate::state::StateStore;
 use crate::twitch;
 use cached::UnboundCache;
 use log::info;
@@ -28,19 +32,78 @@ lazy_static! {
 pub async fn find_valorant_matches_by_players(
     puuids: &HashSet<String>,
     interval: (OffsetDateTime, OffsetDateTime),
-    vod_id: usize,
+    vod_id: &str,
     force: bool,
-) -> Result<Vec<MatchDetailsV1>, RequestError> {
+    cache_dir: &Path,
+    state: &dyn StateStore,
+) -> Result<Vec<MatchDetailsV1>, Error> {
     let mut all_matches = vec![];
     for puuid in puuids {
         all_matches.extend(
-            get_valorant_matches_by_player(puuid, interval.0, interval.1, vod_id, force).await?,
+            get_valorant_matches_by_player(
+                puuid, interval.0, interval.1, vod_id, force, cache_dir, state,
+            )
+            .await?,
         );
     }
     Ok(all_matches)
 }
 
-pub(crate) fn get_match_length(valo_match: &MatchDetailsV1) -> u64 {
+/// Offline counterpart of [`find_valorant_matches_by_players`] working on already loaded matches.
+pub fn find_valorant_matches_offline(
+    matches: Vec<MatchDetailsV1>,
+    puuids: &HashSet<String>,
+    interval: (OffsetDateTime, OffsetDateTime),
+    vod_id: &str,
+    force: bool,
+    state: &dyn StateStore,
+) -> Vec<MatchDetailsV1> {
+    matches
+        .into_iter()
+        .filter(|m| m.players.iter().any(|p| puuids.contains(&p.puuid)))
+        .filter(|m| {
+            is_in_interval(
+                (m.match_info.game_start_millis / 1000) as i64,
+                interval.0,
+                interval.1,
+            )
+        })
+        .filter(|m| force || !state.is_marked(vod_id, m.match_info.match_id))
+        .collect()
+}
+
+/// Loads match details from JSON files. Directories are searched for `*.json` files.
+pub fn load_match_details(paths: &[PathBuf]) -> std::io::Result<Vec<MatchDetailsV1>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|ext| ext == "json") {
+                    files.push(entry);
+                }
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    info!("Loading {} match files", files.len());
+    files
+        .iter()
+        .map(|file| {
+            let match_details = std::fs::read_to_string(file)?;
+            serde_json::from_str(&match_details).map_err(std::io::Error::other)
+        })
+        .collect()
+}
+
+fn is_in_interval(game_start_secs: i64, start: OffsetDateTime, end: OffsetDateTime) -> bool {
+    (start - Duration::from_secs(60)).unix_timestamp() < game_start_secs
+        && game_start_secs < end.unix_timestamp()
+}
+
+pub fn get_match_length(valo_match: &MatchDetailsV1) -> u64 {
     valo_match
         .match_info
         .game_length_millis
@@ -51,9 +114,11 @@ async fn get_valorant_matches_by_player(
     puuid: &str,
     start: OffsetDateTime,
     end: OffsetDateTime,
-    vod_id: usize,
+    vod_id: &str,
     force: bool,
-) -> Result<Vec<MatchDetailsV1>, RequestError> {
+    cache_dir: &Path,
+    state: &dyn StateStore,
+) -> Result<Vec<MatchDetailsV1>, Error> {
     info!("Searching valorant matches for {}", puuid);
     let http_client = Client::new();
     let credentials_manager = CredentialsManager {
@@ -61,24 +126,14 @@ async fn get_valorant_matches_by_player(
     };
 
     let region = get_region(&credentials_manager, &http_client, puuid).await?;
-    let matches = get_matches(&http_client, &credentials_manager, puuid, region).await?;
+    let matches = get_matches(&http_client, &credentials_manager, puuid, region)
+        .await
+        .map_err(|e| Error::Discovery(format!("Failed to get match list: {e:?}")))?;
 
     let matches: Vec<&MatchListsEntry> = matches
         .iter()
-        .filter(|m| {
-            (start - Duration::from_secs(60)).unix_timestamp()
-                < m.game_start_time_millis.timestamp()
-                && m.game_start_time_millis.timestamp() < end.unix_timestamp()
-        })
-        .filter(|m| {
-            let is_processed = Path::new("/processed")
-                .join(format!("{}-{}", vod_id, m.match_id))
-                .exists();
-            let is_failed = Path::new("/failed")
-                .join(format!("{}-{}", vod_id, m.match_id))
-                .exists();
-            force || !(is_processed || is_failed)
-        })
+        .filter(|m| is_in_interval(m.game_start_time_millis.timestamp(), start, end))
+        .filter(|m| force || !state.is_marked(vod_id, m.match_id))
         .collect();
 
     let mut matches_data = vec![];
@@ -89,8 +144,9 @@ async fn get_valorant_matches_by_player(
                 &credentials_manager,
                 region,
                 valo_match.match_id,
+                cache_dir,
             )
-            .await,
+            .await?,
         );
     }
     Ok(matches_data)
@@ -101,11 +157,14 @@ async fn get_match_details(
     credentials_manager: &CredentialsManager,
     region: Region,
     match_id: Uuid,
-) -> MatchDetailsV1 {
-    let save_path = Path::new("matches/").join(format!("{match_id}.json"));
+    cache_dir: &Path,
+) -> Result<MatchDetailsV1, Error> {
+    let save_path = cache_dir.join(format!("{match_id}.json"));
     if save_path.exists() {
-        let match_details = std::fs::read_to_string(save_path).expect("Failed to read match file");
-        return serde_json::from_str(&match_details).expect("Failed to parse match file");
+        let match_details = std::fs::read_to_string(save_path)
+            .map_err(|e| Error::Discovery(format!("Failed to read match file: {e}")))?;
+        return serde_json::from_str(&match_details)
+            .map_err(|e| Error::Discovery(format!("Failed to parse match file: {e}")));
     }
     let result = valorant_api_official::get_match_details_v1(
         credentials_manager,
@@ -114,11 +173,11 @@ async fn get_match_details(
         &match_id,
     )
     .await
-    .expect("Failed to get match details");
-    std::fs::create_dir_all(save_path.parent().unwrap()).expect("Failed to create match directory");
-    std::fs::write(save_path, serde_json::to_string_pretty(&result).unwrap())
-        .expect("Failed to write match file");
-    result
+    .map_err(|e| Error::Discovery(format!("Failed to get match details: {e:?}")))?;
+    std::fs::create_dir_all(save_path.parent().unwrap())
+        .and_then(|_| std::fs::write(save_path, serde_json::to_string_pretty(&result).unwrap()))
+        .map_err(|e| Error::Discovery(format!("Failed to write match file: {e}")))?;
+    Ok(result)
 }
 
 async fn get_matches(
@@ -136,15 +195,20 @@ async fn get_region(
     credentials_manager: &CredentialsManager,
     http_client: &Client,
     puuid: &str,
-) -> Result<Region, RequestError> {
-    valorant_api_official::get_active_shards_v1(credentials_manager, http_client, "europe", puuid)
-        .await
-        .map(|shard| {
-            Region::from_str(&shard.active_shard.to_string()).expect("Failed to get region")
-        })
+) -> Result<Region, Error> {
+    let shard = valorant_api_official::get_active_shards_v1(
+        credentials_manager,
+        http_client,
+        "europe",
+        puuid,
+    )
+    .await
+    .map_err(|e| Error::Discovery(format!("Failed to get active shard: {e:?}")))?;
+    Region::from_str(&shard.active_shard.to_string())
+        .map_err(|_| Error::Discovery(format!("Unknown region: {}", shard.active_shard)))
 }
 
-pub(crate) async fn get_puuid(riot_id: &str) -> Result<String, RequestError> {
+pub async fn get_puuid(riot_id: &str) -> Result<String, Error> {
     let http_client = Client::new();
     let credentials_manager = CredentialsManager {
         api_key: API_KEY.clone(),
@@ -152,7 +216,9 @@ pub(crate) async fn get_puuid(riot_id: &str) -> Result<String, RequestError> {
     if riot_id.len() == 78 {
         return Ok(riot_id.to_string());
     }
-    let (name, tag) = riot_id.split_once('#').expect("Failed to split riot id");
+    let (name, tag) = riot_id
+        .split_once('#')
+        .ok_or_else(|| Error::Discovery(format!("Invalid riot id: {riot_id}")))?;
 
     valorant_api_official::get_accounts_by_name_v1(
         &credentials_manager,
@@ -163,9 +229,23 @@ pub(crate) async fn get_puuid(riot_id: &str) -> Result<String, RequestError> {
     )
     .await
     .map(|accounts| accounts.puuid)
+    .map_err(|e| Error::Discovery(format!("Failed to get puuid of {riot_id}: {e:?}")))
+}
+
+/// Resolves a Riot ID from the players of already loaded matches.
+pub fn get_puuid_offline(riot_id: &str, matches: &[MatchDetailsV1]) -> Option<String> {
+    if riot_id.len() == 78 {
+        return Some(riot_id.to_string());
+    }
+    let (name, tag) = riot_id.split_once('#')?;
+    matches
+        .iter()
+        .flat_map(|m| m.players.iter())
+        .find(|p| p.game_name.eq_ignore_ascii_case(name) && p.tag_line.eq_ignore_ascii_case(tag))
+        .map(|p| p.puuid.clone())
 }
 
-pub(crate) fn get_match_kills(valo_match: &MatchDetailsV1) -> Vec<PlayerRoundKill> {
+pub fn get_match_kills(valo_match: &MatchDetailsV1) -> Vec<PlayerRoundKill> {
     valo_match
         .round_results
         .clone()
@@ -176,6 +256,18 @@ pub(crate) fn get_match_kills(valo_match: &MatchDetailsV1) -> Vec<PlayerRoundKil
         .collect()
 }
 
+/// The game time the round started at, estimated from its kills. `None` for rounds without
+/// kills.
+pub fn get_round_start_millis(round: &RoundResult) -> Option<u64> {
+    let starts = round
+        .player_stats
+        .iter()
+        .flat_map(|p| &p.kills)
+        .map(|k| k.time_since_game_start_millis - k.time_since_round_start_millis)
+        .collect::<Vec<_>>();
+    (!starts.is_empty()).then(|| starts.iter().sum::<u64>() / starts.len() as u64)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct APIData<T> {
     data: T,
@@ -193,7 +285,7 @@ struct AgentData {
     result = true,
     convert = r#"{ format!("{}", agent_uuid) }"#
 )]
-pub(crate) async fn get_agent_name(agent_uuid: Uuid) -> reqwest::Result<String> {
+pub async fn get_agent_name(agent_uuid: Uuid) -> reqwest::Result<String> {
     let url = format!("https://valorant-api.com/v1/agents/{agent_uuid}");
     let response: APIData<AgentData> = reqwest::get(url).await?.json().await?;
     Ok(response.data.display_name)
@@ -211,7 +303,7 @@ struct WeaponData {
     result = true,
     convert = r#"{ format!("{}", weapon_uuid) }"#
 )]
-pub(crate) async fn get_weapon_name(weapon_uuid: Uuid) -> reqwest::Result<String> {
+pub async fn get_weapon_name(weapon_uuid: Uuid) -> reqwest::Result<String> {
     let url = format!("https://valorant-api.com/v1/weapons/{weapon_uuid}");
     let response: APIData<WeaponData> = reqwest::get(url).await?.json().await?;
     Ok(response.data.display_name)
@@ -230,7 +322,7 @@ struct MapData {
     result = true,
     convert = r#"{ format!("{}", map_url) }"#
 )]
-pub(crate) async fn get_map_name(map_url: &str) -> reqwest::Result<Option<String>> {
+pub async fn get_map_name(map_url: &str) -> reqwest::Result<Option<String>> {
     let url = "https://valorant-api.com/v1/maps";
     let response: APIData<Vec<MapData>> = reqwest::get(url).await?.json().await?;
     Ok(response
@@ -249,7 +341,7 @@ pub fn save_match_video(
     twitch::download_vod(vod_id, match_video_path, start, end)
 }
 
-pub(crate) fn get_agent(valo_match: &MatchDetailsV1, puuid: &str) -> Option<Uuid> {
+pub fn get_agent(valo_match: &MatchDetailsV1, puuid: &str) -> Option<Uuid> {
     valo_match
         .players
         .iter()
@@ -1,14 +1,13 @@
+use crate::config::{Debounce, Sampling};
+use crate::detector::KillDetector;
+use crate::error::Error;
 use crate::valorant;
 use ffmpeg_sidecar::command::FfmpegCommand;
-use ffmpeg_sidecar::event::{FfmpegEvent, OutputVideoFrame};
-use kdam::tqdm;
-use lazy_static::lazy_static;
+use itertools::Itertools;
+use kdam::{tqdm, BarExt};
 use log::{debug, warn};
-use ndarray::{Array, ArrayBase, CowArray, CowRepr};
-use ort::environment::Environment;
-use ort::{GraphOptimizationLevel, LoggingLevel, OrtResult, Session, SessionBuilder, Value};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::time::Duration;
 use time::OffsetDateTime;
 use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
@@ -16,41 +15,71 @@ use valorant_api_official::response_types::matchdetails_v1::MatchDetailsV1;
 const VIDEO_MATCH_SPLIT_THRESHOLD: u64 = 5 * 60 * 1000;
 
 const VIDEO_ANALYSIS_RATE: usize = 3;
+/// Frames run through the detector at once.
+const INFERENCE_BATCH: usize = 256;
+/// Frame rate of the refinement pass around single events.
+const REFINEMENT_RATE: usize = 15;
+/// Parts of the match video sampled by [`probe_signal`].
+const PROBE_WINDOWS: u32 = 6;
+const PROBE_WINDOW: Duration = Duration::from_secs(20);
+/// Frames a kill banner stays visible for at most.
+const MAX_BANNER_FRAMES: usize = 6 * VIDEO_ANALYSIS_RATE;
 
-lazy_static! {
-    static ref ORT_ENVIRONMENT: Arc<Environment> = Environment::builder()
-        .with_name("clip-cutter")
-        .with_log_level(LoggingLevel::Error)
-        .build()
-        .expect("Could not create environment")
-        .into_arc();
-    static ref ORT_SESSION: Session = SessionBuilder::new(&ORT_ENVIRONMENT)
-        .expect("Could not create session builder")
-        .with_optimization_level(GraphOptimizationLevel::Level3)
-        .expect("Could not set optimization level")
-        .with_model_from_file("model.onnx")
-        .expect("Could not load model");
-}
-
-pub(crate) fn get_match_interval(
-    video_start: OffsetDateTime,
+/// Stops the coarse pass of [`detect_anchor_scores`] once `debounce` finds `max_events`
+/// events, the debounce alignment extracts the events with later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EarlyStop {
+    pub max_events: usize,
+    pub debounce: Debounce,
+}
+
+/// The part of a VOD that is cut out as match video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchWindow {
+    /// Start of the match video in the VOD.
+    pub start: Duration,
+    /// End of the match video in the VOD.
+    pub end: Duration,
+    /// How much of the match happened before the VOD started and is missing from the match
+    /// video.
+    pub missing_start: Duration,
+}
+
+/// Returns the part of the VOD covering `valo_match`, cut to the VOD if the match started
+/// before or ended after it.
+pub fn get_match_interval(
+    vod_interval: (OffsetDateTime, OffsetDateTime),
     valo_match: &MatchDetailsV1,
-) -> Result<(Duration, Duration), String> {
-    let start = get_video_offset(video_start, valo_match)?;
-    let end = start
-        + Duration::from_millis(
-            valorant::get_match_length(valo_match) + VIDEO_MATCH_SPLIT_THRESHOLD,
+) -> Result<MatchWindow, Error> {
+    let vod_start = vod_interval.0.unix_timestamp() * 1000;
+    let vod_length = (vod_interval.1 - vod_interval.0).whole_milliseconds() as i64;
+    let start = valo_match.match_info.game_start_millis as i64 - vod_start;
+    let end = start + (valorant::get_match_length(valo_match) + VIDEO_MATCH_SPLIT_THRESHOLD) as i64;
+    let window = MatchWindow {
+        start: Duration::from_millis(start.max(0) as u64),
+        end: Duration::from_millis(end.clamp(0, vod_length) as u64),
+        missing_start: Duration::from_millis((-start).max(0) as u64),
+    };
+    if window.end <= window.start {
+        return Err(Error::Download("Match is not in the video".to_string()));
+    }
+    if !window.missing_start.is_zero() || end > vod_length {
+        warn!(
+            "Match is only partially in the video, missing {:?} at the start",
+            window.missing_start
         );
-    Ok((start, end))
+    }
+    Ok(window)
 }
 
-pub(crate) struct Metadata {
-    pub(crate) track: String,
-    pub(crate) title: String,
-    pub(crate) episode_id: String,
-    pub(crate) album: String,
-    pub(crate) description: String,
-    pub(crate) genre: String,
+pub struct Metadata {
+    pub track: String,
+    pub title: String,
+    pub episode_id: String,
+    pub album: String,
+    pub description: String,
+    pub genre: String,
+    pub comment: String,
 }
 
 impl IntoIterator for Metadata {
@@ -65,12 +94,13 @@ impl IntoIterator for Metadata {
             ("album".to_string(), self.album),
             ("description".to_string(), self.description),
             ("genre".to_string(), self.genre),
+            ("comment".to_string(), self.comment),
         ]
         .into_iter()
     }
 }
 
-pub(crate) fn split_video(
+pub fn split_video(
     path: &Path,
     out_path: &Path,
     start: Duration,
@@ -96,26 +126,21 @@ pub(crate) fn split_video(
         metadata,
         out_path.to_str().unwrap(),
     );
-    std::process::Command::new("sh")
+    let output = std::process::Command::new("sh")
         .arg("-c")
         .arg(ffmpeg_split_command)
         .output()?;
-    Ok(Path::new(&out_path).to_path_buf())
-}
-
-fn get_video_offset(
-    video_start: OffsetDateTime,
-    valo_match: &MatchDetailsV1,
-) -> Result<Duration, String> {
-    let match_start = valo_match.match_info.game_start_millis;
-    let video_start = video_start.unix_timestamp() as u64 * 1000;
-    match match_start > video_start {
-        true => Ok(Duration::from_millis(match_start - video_start)),
-        false => Err("Video start is after match start".to_string()),
+    if !output.status.success() || !out_path.exists() {
+        return Err(std::io::Error::other(format!(
+            "ffmpeg failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
     }
+    Ok(Path::new(&out_path).to_path_buf())
 }
 
-pub(crate) fn format_ffmpeg_time(time: Duration, with_millis: bool) -> String {
+pub fn format_ffmpeg_time(time: Duration, with_millis: bool) -> String {
     let millis = time.as_millis();
     let hours = millis / 3600 / 1000;
     let minutes = millis / 60 / 1000 % 60;
@@ -128,102 +153,396 @@ pub(crate) fn format_ffmpeg_time(time: Duration, with_millis: bool) -> String {
     }
 }
 
-pub(crate) fn detect_kill_events(
+/// Extracts the events from the detector `scores` of an anchor: the times the anchor became
+/// visible for `min_duration_millis` after being gone for `rearm_gap_millis`, and with a
+/// `stack_rise` the times a new banner stacked onto a visible one. The times are moved by
+/// `min_offset_millis`.
+pub fn detect_kill_events(
     min_offset_millis: u64,
-    consecutive_kills: i32,
-    kill_timestamps: &Vec<(f32, bool)>,
+    debounce: &Debounce,
+    scores: &[(f32, f32)],
 ) -> Vec<Duration> {
-    let mut first_stamp = None;
-    let mut wait_for_toggle = false;
-    let mut kills = vec![];
-    let mut num_consecutive_detections = 0;
-    let mut num_consecutive_non_detections = 0;
-    for (timestamp, has_kill) in kill_timestamps {
-        if wait_for_toggle {
-            if !has_kill {
-                num_consecutive_non_detections += 1;
-                if num_consecutive_non_detections > 2 * VIDEO_ANALYSIS_RATE {
-                    wait_for_toggle = false;
-                    num_consecutive_non_detections = 0;
-                }
-            } else {
-                num_consecutive_non_detections = 0;
-            }
-            continue;
+    let mut extractor = EventExtractor::new(debounce);
+    extractor.extend(scores);
+    extractor
+        .events
+        .into_iter()
+        .map(|timestamp| {
+            Duration::from_secs_f32(timestamp) + Duration::from_millis(min_offset_millis)
+        })
+        .collect()
+}
+
+/// The state of [`detect_kill_events`] between frames, so scores can be fed as they come in.
+struct EventExtractor<'a> {
+    debounce: &'a Debounce,
+    /// Timestamps of the events found so far.
+    events: Vec<f32>,
+    /// Start of the visible stretch that may become the next event.
+    onset: Option<f32>,
+    armed: bool,
+    last_visible: Option<f32>,
+    /// Lowest visible score since the last event, a stacked banner rises above it.
+    trough: f32,
+}
+
+impl<'a> EventExtractor<'a> {
+    fn new(debounce: &'a Debounce) -> Self {
+        Self {
+            debounce,
+            events: vec![],
+            onset: None,
+            armed: true,
+            last_visible: None,
+            trough: f32::INFINITY,
+        }
+    }
+
+    fn extend(&mut self, scores: &[(f32, f32)]) {
+        for &(timestamp, score) in scores {
+            self.push(timestamp, score);
         }
+    }
 
-        if !has_kill {
-            if num_consecutive_detections > 0 {
-                warn!("Found kill event but no longer detecting");
+    fn push(&mut self, timestamp: f32, score: f32) {
+        let debounce = self.debounce;
+        if score <= debounce.threshold {
+            if self.onset.take().is_some() {
+                debug!("Anchor disappeared before the minimum duration");
             }
-            num_consecutive_detections = 0;
-            continue;
+            let rearm_gap = debounce.rearm_gap_millis as f32 / 1000.;
+            if !self.armed
+                && self
+                    .last_visible
+                    .is_some_and(|last| timestamp - last >= rearm_gap)
+            {
+                self.armed = true;
+            }
+            // a dropout within a banner is no trough to rise from
+            return;
         }
+        self.last_visible = Some(timestamp);
 
-        first_stamp = first_stamp.or(Some(timestamp));
-        num_consecutive_detections += 1;
+        if !self.armed {
+            if debounce
+                .stack_rise
+                .is_some_and(|rise| score - self.trough >= rise)
+            {
+                debug!("Found stacked event");
+                self.events.push(timestamp);
+                self.trough = score;
+            } else {
+                self.trough = self.trough.min(score);
+            }
+            return;
+        }
 
-        if num_consecutive_detections > consecutive_kills {
+        let start = *self.onset.get_or_insert(timestamp);
+        if timestamp - start >= debounce.min_duration_millis as f32 / 1000. {
             debug!("Found kill event");
-            kills.push(
-                Duration::from_secs_f32(*first_stamp.unwrap())
-                    + Duration::from_millis(min_offset_millis),
+            self.events.push(start);
+            self.onset = None;
+            self.armed = false;
+            self.trough = score;
+        }
+    }
+}
+
+/// Runs the `detector` on the area cut out by `crop` of the match video from
+/// `min_offset_millis`, returns the frame timestamps since then and the detector scores. With
+/// `early_stop`, the coarse pass stops once all events are found, see [`EarlyStop`]. With a
+/// fine rate in `sampling`, the frames around the ones scoring above `threshold` are replaced
+/// by a pass at that rate.
+#[allow(clippy::too_many_arguments)]
+pub fn detect_anchor_scores(
+    path: &Path,
+    detector: &dyn KillDetector,
+    crop: &str,
+    min_offset_millis: u64,
+    bar_position: u16,
+    threshold: f32,
+    early_stop: Option<&EarlyStop>,
+    sampling: &Sampling,
+) -> Result<Vec<(f32, f32)>, Error> {
+    let seek = Duration::from_millis(min_offset_millis);
+    let mut command = detection_command(path, crop, seek, None, sampling.coarse_rate);
+    let mut pb = tqdm!(desc = "Detecting anchors", position = bar_position);
+    let mut extractor = early_stop.map(|early_stop| EventExtractor::new(&early_stop.debounce));
+    let coarse = stream_detections(&mut command, detector, |batch, detections| {
+        pb.update(batch).ok();
+        let (Some(early_stop), Some(extractor)) = (early_stop, extractor.as_mut()) else {
+            return false;
+        };
+        // only the new frames of the batch are fed
+        extractor.extend(&detections[detections.len() - batch..]);
+        let stop = extractor.events.len() >= early_stop.max_events;
+        if stop {
+            debug!(
+                "Found all {} events, stopping detection",
+                early_stop.max_events
             );
-            first_stamp = None;
-            wait_for_toggle = true;
-            num_consecutive_detections = 0;
         }
+        stop
+    })?;
+    let Some(fine_rate) = sampling.fine_rate else {
+        return Ok(coarse);
+    };
+
+    let padding = sampling.fine_window_millis as f32 / 1000.;
+    let windows = candidate_windows(&coarse, threshold, padding);
+    debug!(
+        "Scanning {} candidate windows at {} fps",
+        windows.len(),
+        fine_rate
+    );
+    let mut detections = coarse
+        .into_iter()
+        .filter(|(timestamp, _)| {
+            !windows
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(timestamp))
+        })
+        .collect::<Vec<_>>();
+    for (start, end) in windows {
+        let mut command = detection_command(
+            path,
+            crop,
+            seek + Duration::from_secs_f32(start),
+            Some(Duration::from_secs_f32(end - start)),
+            fine_rate,
+        );
+        let fine = stream_detections(&mut command, detector, |batch, _| {
+            pb.update(batch).ok();
+            false
+        })?;
+        detections.extend(
+            fine.into_iter()
+                .map(|(timestamp, score)| (start + timestamp, score)),
+        );
     }
-    kills
+    detections.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(detections)
 }
 
-pub(crate) fn detect_kill_timestamps(path: &Path, min_offset_millis: u64) -> Vec<(f32, bool)> {
+/// The merged windows of `padding` seconds around the frames of `scores` above `threshold`.
+fn candidate_windows(scores: &[(f32, f32)], threshold: f32, padding: f32) -> Vec<(f32, f32)> {
+    let mut windows: Vec<(f32, f32)> = vec![];
+    for (timestamp, _) in scores.iter().filter(|(_, score)| *score > threshold) {
+        let (start, end) = ((timestamp - padding).max(0.), timestamp + padding);
+        match windows.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => windows.push((start, end)),
+        }
+    }
+    windows
+}
+
+/// Runs the kill `detector` on the area cut out by `crop` of `length` of the match video from
+/// `start` at [`REFINEMENT_RATE`] and returns the times in the match video at which kill
+/// banners appear, scoring above `threshold`.
+pub fn detect_kill_onsets(
+    path: &Path,
+    detector: &dyn KillDetector,
+    crop: &str,
+    start: Duration,
+    length: Duration,
+    threshold: f32,
+) -> Result<Vec<Duration>, Error> {
+    let mut command = detection_command(path, crop, start, Some(length), REFINEMENT_RATE as f32);
+    let scores = stream_detections(&mut command, detector, |_, _| false)?;
+    let detections = visible(&scores, threshold);
+    let onsets = detections
+        .iter()
+        .zip(
+            [false]
+                .iter()
+                .chain(detections.iter().map(|(_, has_kill)| has_kill)),
+        )
+        .filter(|((_, has_kill), had_kill)| *has_kill && !**had_kill)
+        .map(|((timestamp, _), _)| start + Duration::from_secs_f32(*timestamp))
+        .collect();
+    Ok(onsets)
+}
+
+/// How clearly the kill `detector` sees banners in the area cut out by `crop`, measured on
+/// [`PROBE_WINDOWS`] parts of the match video of `length`. Counts the banners that disappear
+/// again as a banner would, minus the visible stretches too long to be one. Frames scoring above
+/// `threshold` show a banner.
+pub fn probe_signal(
+    path: &Path,
+    detector: &dyn KillDetector,
+    crop: &str,
+    length: Duration,
+    threshold: f32,
+) -> Result<i64, Error> {
+    let mut signal = 0;
+    for i in 1..=PROBE_WINDOWS {
+        let start = length * i / (PROBE_WINDOWS + 1);
+        let mut command = detection_command(
+            path,
+            crop,
+            start,
+            Some(PROBE_WINDOW),
+            VIDEO_ANALYSIS_RATE as f32,
+        );
+        let scores = stream_detections(&mut command, detector, |_, _| false)?;
+        let detections = visible(&scores, threshold);
+        for (is_visible, run) in &detections.iter().chunk_by(|(_, visible)| *visible) {
+            if is_visible {
+                signal += match run.count() {
+                    frames if frames <= MAX_BANNER_FRAMES => 1,
+                    _ => -1,
+                };
+            }
+        }
+    }
+    Ok(signal)
+}
+
+/// Writes the frame of the video at `path` at `time` through `filter` as an image to
+/// `out_path`, the format is taken from its extension.
+pub fn save_frame(path: &Path, filter: &str, time: Duration, out_path: &Path) -> Result<(), Error> {
     let mut command = FfmpegCommand::new();
     command
-        .hwaccel("auto")
-        .seek(format!("{min_offset_millis}ms"))
+        .seek(format!("{}ms", time.as_millis()))
         .input(path.to_str().unwrap())
-        .rate(VIDEO_ANALYSIS_RATE as f32)
-        .filter("mpdecimate,crop=200:200:in_w/2-100:0.7*in_h,scale=50:50")
+        .filter(filter)
+        .frames(1)
+        .overwrite()
+        .output(out_path.to_str().unwrap());
+    debug!("Running command: {:?}", command);
+    let status = command
+        .spawn()
+        .map_err(|e| Error::Detection(format!("Failed to start ffmpeg: {e}")))?
+        .wait()
+        .map_err(|e| Error::Detection(format!("Failed to run ffmpeg: {e}")))?;
+    if !status.success() || !out_path.exists() {
+        return Err(Error::Detection(format!(
+            "No frame at {}ms in {}",
+            time.as_millis(),
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// The ffmpeg command decoding the area cut out by `crop` from `seek` at `rate` frames per
+/// second, `length` long or to the end of the video.
+fn detection_command(
+    path: &Path,
+    crop: &str,
+    seek: Duration,
+    length: Option<Duration>,
+    rate: f32,
+) -> FfmpegCommand {
+    let mut command = FfmpegCommand::new();
+    command
+        .hwaccel("auto")
+        .seek(format!("{}ms", seek.as_millis()))
+        .input(path.to_str().unwrap());
+    // output options only apply before the output that `rawvideo` adds
+    if let Some(length) = length {
+        command.duration(format!("{}ms", length.as_millis()));
+    }
+    command
+        .rate(rate)
+        .filter(format!("mpdecimate,{crop}"))
         .no_audio()
         .rawvideo();
-    debug!("Running command: {:?}", command);
-    let mut process = command.spawn().unwrap();
-    let video = process.iter().unwrap();
+    command
+}
 
-    let frames = tqdm!(video, desc = "Collecting frames")
-        .filter_map(|frame| match frame {
-            FfmpegEvent::OutputFrame(f) => Some(f),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
+/// Runs `command` and streams its frames through `detector` in batches of
+/// [`INFERENCE_BATCH`], so only one batch is held in memory. Returns the timestamp and score
+/// of each frame. After each batch `on_batch` gets the batch size and the scores so far,
+/// ffmpeg is stopped early if it returns true.
+fn stream_detections(
+    command: &mut FfmpegCommand,
+    detector: &dyn KillDetector,
+    mut on_batch: impl FnMut(usize, &[(f32, f32)]) -> bool,
+) -> Result<Vec<(f32, f32)>, Error> {
+    debug!("Running command: {:?}", command);
+    let mut process = command
+        .spawn()
+        .map_err(|e| Error::Detection(format!("Failed to start ffmpeg: {e}")))?;
+    let frames = process
+        .iter()
+        .map_err(|e| Error::Detection(format!("Failed to read ffmpeg output: {e}")))?
+        .filter_frames();
+    let mut detections = vec![];
+    for batch in &frames.chunks(INFERENCE_BATCH) {
+        let batch = batch.collect::<Vec<_>>();
+        let scores = detector.scores(&batch)?;
+        detections.extend(batch.iter().map(|frame| frame.timestamp).zip(scores));
+        if on_batch(batch.len(), &detections) {
+            process.kill().ok();
+            break;
+        }
+    }
+    Ok(detections)
+}
 
-    let kill_timestamps = frames
+/// Whether the anchor is visible at each timestamp of `scores`, scoring above `threshold`, see
+/// [`KillDetector::scores`].
+fn visible(scores: &[(f32, f32)], threshold: f32) -> Vec<(f32, bool)> {
+    scores
         .iter()
-        .map(|f| f.timestamp)
-        .zip(have_kills(&frames).unwrap())
-        .collect::<Vec<_>>();
-    kill_timestamps
+        .map(|(timestamp, score)| (*timestamp, *score > threshold))
+        .collect()
 }
 
-fn have_kills(frames: &[OutputVideoFrame]) -> OrtResult<Vec<bool>> {
-    let array = Array::from_shape_vec(
-        (frames.len(), 3 * 50 * 50),
-        frames
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scores at [`VIDEO_ANALYSIS_RATE`] frames per second.
+    fn at_analysis_rate(scores: &[f32]) -> Vec<(f32, f32)> {
+        scores
             .iter()
-            .flat_map(|frame| {
-                frame
-                    .data
-                    .iter()
-                    .map(|v| *v as f32 / 255.)
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>(),
-    )
-    .expect("Failed to create array");
-    let array: ArrayBase<CowRepr<'_, f32>, _> = CowArray::from(array).into_dyn();
-    let tensor = Value::from_array(ORT_SESSION.allocator(), &array)?;
-    let outputs = ORT_SESSION.run(vec![tensor])?;
-    let output = outputs.first().unwrap().try_extract()?;
-    Ok(output.view().iter().map(|v: &i64| *v > 0).collect())
+            .enumerate()
+            .map(|(i, score)| (i as f32 / VIDEO_ANALYSIS_RATE as f32, *score))
+            .collect()
+    }
+
+    fn stacking() -> Debounce {
+        Debounce {
+            stack_rise: Some(1.),
+            ..Debounce::default()
+        }
+    }
+
+    #[test]
+    fn dropout_within_banner_is_not_stacked() {
+        let scores = at_analysis_rate(&[0., 1., 1., 0., 1., 1., 1., 0., 0., 0.]);
+        let events = detect_kill_events(0, &stacking(), &scores);
+        assert_eq!(events, [Duration::from_secs_f32(1. / 3.)]);
+    }
+
+    #[test]
+    fn rising_score_is_stacked() {
+        let scores = at_analysis_rate(&[-1., 1., 1., 2.5, 2.5, -1., -1.]);
+        let events = detect_kill_events(0, &stacking(), &scores);
+        assert_eq!(
+            events,
+            [
+                Duration::from_secs_f32(1. / 3.),
+                Duration::from_secs_f32(1.)
+            ]
+        );
+    }
+
+    #[test]
+    fn banner_after_rearm_gap_is_new_event() {
+        let mut scores = vec![1., 1.];
+        scores.extend([-1.; 3 * VIDEO_ANALYSIS_RATE]);
+        scores.extend([1., 1.]);
+        let events = detect_kill_events(1000, &Debounce::default(), &at_analysis_rate(&scores));
+        assert_eq!(
+            events,
+            [
+                Duration::from_secs(1),
+                Duration::from_secs(1) + Duration::from_secs_f32(11. / 3.)
+            ]
+        );
+    }
 }